@@ -0,0 +1,54 @@
+// Defines a tower of words that each call the previous one twice. With
+// bodies copied into their callers this doubled the dictionary with every
+// layer; with calls by index it has to grow linearly.
+
+extern crate forth;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use forth::Forth;
+
+const LAYERS: usize = 1024;
+
+// Tracks the number of bytes currently allocated
+struct Counting;
+
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            LIVE.fetch_add(layout.size(), Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+fn main() {
+    let mut f = Forth::new();
+    f.eval(": w0 1 ;").unwrap();
+
+    let base = LIVE.load(Ordering::Relaxed);
+    let start = Instant::now();
+    println!("{:>8} {:>12} {:>16} {:>12}", "layers", "live bytes", "bytes per layer", "elapsed");
+
+    for n in 1..LAYERS + 1 {
+        f.eval(&format!(": w{} w{} w{} ;", n, n - 1, n - 1)).unwrap();
+
+        if n.is_power_of_two() {
+            let live = LIVE.load(Ordering::Relaxed) - base;
+            println!("{:>8} {:>12} {:>16} {:>12?}", n, live, live / n, start.elapsed());
+        }
+    }
+}
@@ -0,0 +1,70 @@
+// Times the threaded code evaluator against the tree walking one it
+// replaced, kept in tree_walker/, on a few longer numeric scripts. Each
+// script is run several times on a fresh interpreter and the fastest run of
+// each evaluator is reported, along with how many times faster the threaded
+// code was.
+//
+// On a single core virtual machine the threaded code was 1.6 to 2.5 times
+// faster on these scripts, not the order of magnitude first hoped for. Most
+// instructions already cost about as much as pushing and popping a cell, so
+// more would need fewer, larger instructions rather than a tighter loop.
+
+extern crate forth;
+
+use std::fmt::Debug;
+use std::time::{Duration, Instant};
+
+use forth::Forth;
+
+mod tree_walker;
+
+const RUNS: usize = 5;
+
+const SCRIPTS: &[(&str, &str, &str)] = &[
+    ("arithmetic",
+     ": inner 1000 0 do i 3 * 7 + 5 mod drop loop ; : outer 1000 0 do inner loop ;",
+     "outer"),
+    ("calls",
+     ": sq dup * ; : cube dup sq * ; : sum 0 swap 0 do i cube + i sq - loop ; : run 200 0 do 5000 sum drop loop ;",
+     "run"),
+    ("fibonacci",
+     ": fib 0 1 rot 0 ?do tuck + loop drop ; : run 20000 0 do 90 fib drop loop ;",
+     "run"),
+    ("collatz",
+     ": step dup 2 mod if 3 * 1+ else 2/ then ; \
+      : len 0 swap begin dup 1 > while step swap 1+ swap repeat drop ; \
+      : run 0 30000 1 do i len max loop ;",
+     "run"),
+    ("stack",
+     ": shuffle 1 2 3 rot swap over nip tuck 2dup 2swap 2drop 2drop drop drop ; \
+      : run 500000 0 do shuffle loop ;",
+     "run"),
+];
+
+// Fastest of several runs of `run`, each after `setup` on a fresh
+// interpreter made by `new`
+fn time<F, E>(new: fn() -> F, eval: fn(&mut F, &str) -> Result<(), E>, setup: &str, run: &str) -> Duration
+where
+    E: Debug,
+{
+    let mut best = None;
+    for _ in 0..RUNS {
+        let mut f = new();
+        eval(&mut f, setup).unwrap();
+        let start = Instant::now();
+        eval(&mut f, run).unwrap();
+        let elapsed = start.elapsed();
+        best = Some(best.map_or(elapsed, |b: Duration| b.min(elapsed)));
+    }
+    best.unwrap()
+}
+
+fn main() {
+    println!("{:<12} {:>12} {:>12} {:>8}", "script", "tree walker", "threaded", "speedup");
+    for &(name, setup, run) in SCRIPTS {
+        let old = time(tree_walker::Forth::<i32>::new, tree_walker::Forth::eval, setup, run);
+        let new = time(Forth::<i32>::new, Forth::eval, setup, run);
+        let speedup = old.as_secs_f64() / new.as_secs_f64();
+        println!("{:<12} {:>12?} {:>12?} {:>7.1}x", name, old, new, speedup);
+    }
+}
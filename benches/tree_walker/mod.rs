@@ -0,0 +1,865 @@
+// The tree walking evaluator that the threaded code replaced, as it was
+// just before, kept so that benches/eval.rs can time both. Only the path of
+// the `cell` import differs, as this is a module rather than a crate root.
+
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+
+mod cell;
+
+pub use self::cell::{Cell, Overflowing};
+
+// Cell type of `Forth::new()`
+pub type Value = i32;
+pub type ForthResult = Result<(), Error>;
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+enum ArithWord {
+    Add, Sub, Mul, Div, Mod, Min, Max,
+    Eq, Ne, Lt, Gt, ULt,
+    And, Or, Xor, LShift, RShift,
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+enum UnaryWord { Negate, Abs, TwoDiv }
+
+// Divisions taking more than two operands or leaving more than one result
+#[derive(Debug, PartialEq, Copy, Clone)]
+enum DivWord { DivMod, StarSlash, StarSlashMod, FmMod, SmRem }
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+enum StackWord {
+    Dup, Drop, Swap, Over,
+    Rot, MinusRot, Nip, Tuck, Pick, Roll, QDup, Depth,
+    TwoDup, TwoDrop, TwoSwap, TwoOver, TwoRot,
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+enum Symbol {
+    Colon, SemiColon,
+    If, Else, Then,
+    Do, QDo, Loop, PlusLoop, Leave,
+    Begin, Until, While, Repeat, Again,
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+enum LoopWord { Do, I, J, Unloop }
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+enum ReturnWord { ToR, FromR, Fetch, TwoToR, TwoFromR }
+
+// Jump offsets are relative to the branch itself, so a body stays valid
+// when it is spliced into another definition.
+#[derive(Debug, PartialEq, Copy, Clone)]
+enum Branch {
+    Always(isize),
+    IfZero(isize),
+    QDo(isize),         // Skips the loop when limit and index are equal
+    Loop(isize),
+    PlusLoop(isize),
+    Leave(isize),
+}
+
+impl Branch {
+    fn with_offset(self, offset: isize) -> Branch {
+        match self {
+            Branch::Always(_) => Branch::Always(offset),
+            Branch::IfZero(_) => Branch::IfZero(offset),
+            Branch::QDo(_) => Branch::QDo(offset),
+            Branch::Loop(_) => Branch::Loop(offset),
+            Branch::PlusLoop(_) => Branch::PlusLoop(offset),
+            Branch::Leave(_) => Branch::Leave(offset),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+enum Item<C> {
+    Exec_(Exec<C>),
+    Symbol_(Symbol),
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+enum Exec<C> {
+    Arith_(ArithWord),
+    Unary_(UnaryWord),
+    Div_(DivWord),
+    Stack_(StackWord),
+    Value_(C),
+    Branch_(Branch),
+    Loop_(LoopWord),
+    Return_(ReturnWord),
+    Call_(usize),   // Index into the definitions of user-defined words
+}
+
+fn default_word_map<C: Cell>() -> HashMap<String, Vec<Item<C>>> {
+    let mut m = HashMap::new();
+    m.insert("DUP".to_owned(),  vec![Item::Exec_(Exec::Stack_(StackWord::Dup))]);
+    m.insert("DROP".to_owned(), vec![Item::Exec_(Exec::Stack_(StackWord::Drop))]);
+    m.insert("SWAP".to_owned(), vec![Item::Exec_(Exec::Stack_(StackWord::Swap))]);
+    m.insert("OVER".to_owned(), vec![Item::Exec_(Exec::Stack_(StackWord::Over))]);
+    m.insert("ROT".to_owned(),  vec![Item::Exec_(Exec::Stack_(StackWord::Rot))]);
+    m.insert("-ROT".to_owned(), vec![Item::Exec_(Exec::Stack_(StackWord::MinusRot))]);
+    m.insert("NIP".to_owned(),  vec![Item::Exec_(Exec::Stack_(StackWord::Nip))]);
+    m.insert("TUCK".to_owned(), vec![Item::Exec_(Exec::Stack_(StackWord::Tuck))]);
+    m.insert("PICK".to_owned(), vec![Item::Exec_(Exec::Stack_(StackWord::Pick))]);
+    m.insert("ROLL".to_owned(), vec![Item::Exec_(Exec::Stack_(StackWord::Roll))]);
+    m.insert("?DUP".to_owned(), vec![Item::Exec_(Exec::Stack_(StackWord::QDup))]);
+    m.insert("DEPTH".to_owned(), vec![Item::Exec_(Exec::Stack_(StackWord::Depth))]);
+    m.insert("2DUP".to_owned(), vec![Item::Exec_(Exec::Stack_(StackWord::TwoDup))]);
+    m.insert("2DROP".to_owned(), vec![Item::Exec_(Exec::Stack_(StackWord::TwoDrop))]);
+    m.insert("2SWAP".to_owned(), vec![Item::Exec_(Exec::Stack_(StackWord::TwoSwap))]);
+    m.insert("2OVER".to_owned(), vec![Item::Exec_(Exec::Stack_(StackWord::TwoOver))]);
+    m.insert("2ROT".to_owned(), vec![Item::Exec_(Exec::Stack_(StackWord::TwoRot))]);
+    m.insert("+".to_owned(),    vec![Item::Exec_(Exec::Arith_(ArithWord::Add))]);
+    m.insert("-".to_owned(),    vec![Item::Exec_(Exec::Arith_(ArithWord::Sub))]);
+    m.insert("*".to_owned(),    vec![Item::Exec_(Exec::Arith_(ArithWord::Mul))]);
+    m.insert("/".to_owned(),    vec![Item::Exec_(Exec::Arith_(ArithWord::Div))]);
+    m.insert("MOD".to_owned(),  vec![Item::Exec_(Exec::Arith_(ArithWord::Mod))]);
+    m.insert("/MOD".to_owned(), vec![Item::Exec_(Exec::Div_(DivWord::DivMod))]);
+    m.insert("*/".to_owned(),   vec![Item::Exec_(Exec::Div_(DivWord::StarSlash))]);
+    m.insert("*/MOD".to_owned(), vec![Item::Exec_(Exec::Div_(DivWord::StarSlashMod))]);
+    m.insert("FM/MOD".to_owned(), vec![Item::Exec_(Exec::Div_(DivWord::FmMod))]);
+    m.insert("SM/REM".to_owned(), vec![Item::Exec_(Exec::Div_(DivWord::SmRem))]);
+    m.insert("NEGATE".to_owned(), vec![Item::Exec_(Exec::Unary_(UnaryWord::Negate))]);
+    m.insert("ABS".to_owned(),  vec![Item::Exec_(Exec::Unary_(UnaryWord::Abs))]);
+    m.insert("MIN".to_owned(),  vec![Item::Exec_(Exec::Arith_(ArithWord::Min))]);
+    m.insert("MAX".to_owned(),  vec![Item::Exec_(Exec::Arith_(ArithWord::Max))]);
+    m.insert("1+".to_owned(),   vec![Item::Exec_(Exec::Value_(C::from(1))), Item::Exec_(Exec::Arith_(ArithWord::Add))]);
+    m.insert("1-".to_owned(),   vec![Item::Exec_(Exec::Value_(C::from(1))), Item::Exec_(Exec::Arith_(ArithWord::Sub))]);
+    m.insert("2*".to_owned(),   vec![Item::Exec_(Exec::Value_(C::from(1))), Item::Exec_(Exec::Arith_(ArithWord::LShift))]);
+    m.insert("2/".to_owned(),   vec![Item::Exec_(Exec::Unary_(UnaryWord::TwoDiv))]);
+    m.insert("=".to_owned(),    vec![Item::Exec_(Exec::Arith_(ArithWord::Eq))]);
+    m.insert("<>".to_owned(),   vec![Item::Exec_(Exec::Arith_(ArithWord::Ne))]);
+    m.insert("<".to_owned(),    vec![Item::Exec_(Exec::Arith_(ArithWord::Lt))]);
+    m.insert(">".to_owned(),    vec![Item::Exec_(Exec::Arith_(ArithWord::Gt))]);
+    m.insert("U<".to_owned(),   vec![Item::Exec_(Exec::Arith_(ArithWord::ULt))]);
+    m.insert("0=".to_owned(),   vec![Item::Exec_(Exec::Value_(C::from(0))), Item::Exec_(Exec::Arith_(ArithWord::Eq))]);
+    m.insert("0<".to_owned(),   vec![Item::Exec_(Exec::Value_(C::from(0))), Item::Exec_(Exec::Arith_(ArithWord::Lt))]);
+    m.insert("0>".to_owned(),   vec![Item::Exec_(Exec::Value_(C::from(0))), Item::Exec_(Exec::Arith_(ArithWord::Gt))]);
+    m.insert("TRUE".to_owned(), vec![Item::Exec_(Exec::Value_(C::from(-1)))]);
+    m.insert("FALSE".to_owned(), vec![Item::Exec_(Exec::Value_(C::from(0)))]);
+    m.insert("AND".to_owned(),  vec![Item::Exec_(Exec::Arith_(ArithWord::And))]);
+    m.insert("OR".to_owned(),   vec![Item::Exec_(Exec::Arith_(ArithWord::Or))]);
+    m.insert("XOR".to_owned(),  vec![Item::Exec_(Exec::Arith_(ArithWord::Xor))]);
+    m.insert("INVERT".to_owned(), vec![Item::Exec_(Exec::Value_(C::from(-1))), Item::Exec_(Exec::Arith_(ArithWord::Xor))]);
+    m.insert("LSHIFT".to_owned(), vec![Item::Exec_(Exec::Arith_(ArithWord::LShift))]);
+    m.insert("RSHIFT".to_owned(), vec![Item::Exec_(Exec::Arith_(ArithWord::RShift))]);
+    m.insert(":".to_owned(),    vec![Item::Symbol_(Symbol::Colon)]);
+    m.insert(";".to_owned(),    vec![Item::Symbol_(Symbol::SemiColon)]);
+    m.insert("IF".to_owned(),   vec![Item::Symbol_(Symbol::If)]);
+    m.insert("ELSE".to_owned(), vec![Item::Symbol_(Symbol::Else)]);
+    m.insert("THEN".to_owned(), vec![Item::Symbol_(Symbol::Then)]);
+    m.insert("DO".to_owned(),   vec![Item::Symbol_(Symbol::Do)]);
+    m.insert("?DO".to_owned(),  vec![Item::Symbol_(Symbol::QDo)]);
+    m.insert("LOOP".to_owned(), vec![Item::Symbol_(Symbol::Loop)]);
+    m.insert("+LOOP".to_owned(), vec![Item::Symbol_(Symbol::PlusLoop)]);
+    m.insert("LEAVE".to_owned(), vec![Item::Symbol_(Symbol::Leave)]);
+    m.insert("I".to_owned(),    vec![Item::Exec_(Exec::Loop_(LoopWord::I))]);
+    m.insert("J".to_owned(),    vec![Item::Exec_(Exec::Loop_(LoopWord::J))]);
+    m.insert("UNLOOP".to_owned(), vec![Item::Exec_(Exec::Loop_(LoopWord::Unloop))]);
+    m.insert("BEGIN".to_owned(), vec![Item::Symbol_(Symbol::Begin)]);
+    m.insert("UNTIL".to_owned(), vec![Item::Symbol_(Symbol::Until)]);
+    m.insert("WHILE".to_owned(), vec![Item::Symbol_(Symbol::While)]);
+    m.insert("REPEAT".to_owned(), vec![Item::Symbol_(Symbol::Repeat)]);
+    m.insert("AGAIN".to_owned(), vec![Item::Symbol_(Symbol::Again)]);
+    m.insert(">R".to_owned(),   vec![Item::Exec_(Exec::Return_(ReturnWord::ToR))]);
+    m.insert("R>".to_owned(),   vec![Item::Exec_(Exec::Return_(ReturnWord::FromR))]);
+    m.insert("R@".to_owned(),   vec![Item::Exec_(Exec::Return_(ReturnWord::Fetch))]);
+    m.insert("2>R".to_owned(),  vec![Item::Exec_(Exec::Return_(ReturnWord::TwoToR))]);
+    m.insert("2R>".to_owned(),  vec![Item::Exec_(Exec::Return_(ReturnWord::TwoFromR))]);
+    m
+}
+
+// User-defined words compile to a call of their entry in `definitions`,
+// so later redefinitions of a name do not affect words already using it.
+pub struct Forth<C: Cell = Value> {
+    word_map: HashMap<String, Vec<Item<C>>>,
+    definitions: Vec<Vec<Item<C>>>,
+    stack: Vec<C>,
+    loop_stack: Vec<LoopFrame<C>>,
+    return_stack: Vec<C>,
+    step_limit: Option<usize>,
+    overflow: Overflow,
+    transactional: bool,
+}
+
+/// What arithmetic words do when a result does not fit in a cell
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Overflow {
+    Wrapping,       // Keep the low bits, as most Forth systems do
+    Checked,        // Fail with `Error::Overflow`
+    Saturating,     // Clamp to the nearest representable value
+}
+
+impl Overflow {
+    fn narrow<C: Cell>(self, r: Overflowing<C>) -> Result<C, Error> {
+        match self {
+            _ if !r.overflowed => Ok(r.wrapped),
+            Overflow::Wrapping => Ok(r.wrapped),
+            Overflow::Checked => Err(Error::Overflow),
+            Overflow::Saturating => Ok(r.saturated),
+        }
+    }
+}
+
+// Where to resume once a user-defined word returns
+struct Frame {
+    word: Option<usize>,    // None while executing the input itself
+    pc: usize,
+    return_depth: usize,    // Return stack depth on entry to the called word
+}
+
+// Loop-control parameters of an active DO loop
+#[derive(Debug, Copy, Clone)]
+struct LoopFrame<C> {
+    index: C,
+    limit: C,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    DivisionByZero,
+    StackUnderflow,
+    UnknownWord,
+    InvalidWord,
+    StepLimitExceeded,
+    Overflow,
+    ReturnStackImbalance,
+}
+
+enum ParseState {
+    Normal,         // Parse into existing words
+    CustomInit,     // This item is the name of re-defined word
+    Custom,         // This item is the body of re-defined word
+}
+
+// Control structure awaiting resolution in the word being defined
+enum Control {
+    If(usize),      // Index of the IF branch, resolved by ELSE or THEN
+    Else(usize),    // Index of the ELSE branch, resolved by THEN
+    Do(usize, Vec<usize>),  // Index of the DO and of each LEAVE, resolved by LOOP
+    Begin(usize),   // Index of the BEGIN, target of UNTIL, AGAIN and REPEAT
+    While(usize),   // Index of the WHILE branch, resolved by REPEAT
+}
+
+impl<C: Cell> Default for Forth<C> {
+    fn default() -> Forth<C> {
+        Forth {
+            word_map: default_word_map(),
+            definitions: Vec::new(),
+            stack: Vec::new(),
+            loop_stack: Vec::new(),
+            return_stack: Vec::new(),
+            step_limit: None,
+            overflow: Overflow::Wrapping,
+            transactional: false,
+        }
+    }
+}
+
+// Other cell types are available through `Forth::<C>::default()`
+impl Forth {
+    pub fn new() -> Forth {
+        Forth::default()
+    }
+
+    pub fn with_overflow(overflow: Overflow) -> Forth {
+        let mut f = Forth::default();
+        f.set_overflow(overflow);
+        f
+    }
+}
+
+impl<C: Cell> Forth<C> {
+    pub fn set_overflow(&mut self, overflow: Overflow) {
+        self.overflow = overflow;
+    }
+
+    pub fn format_stack(&self) -> String {
+        StackFormat(self).to_string()
+    }
+
+    /// Contents of the return stack, bottom first. After a failed `eval` it
+    /// shows what was left behind at the point of failure.
+    pub fn return_stack(&self) -> &[C] {
+        &self.return_stack
+    }
+
+    /// Limits the number of words a single `eval` may execute, so that
+    /// runaway loops fail with `Error::StepLimitExceeded` instead of hanging.
+    pub fn set_step_limit(&mut self, limit: Option<usize>) {
+        self.step_limit = limit;
+    }
+
+    /// In transactional mode a failing `eval` leaves the data stack and the
+    /// dictionary exactly as they were before the call.
+    pub fn set_transactional(&mut self, transactional: bool) {
+        self.transactional = transactional;
+    }
+
+    pub fn eval(&mut self, input: &str) -> ForthResult {
+        if !self.transactional {
+            return self.run(input);
+        }
+
+        let stack = self.stack.clone();
+        let word_map = self.word_map.clone();
+        let definitions = self.definitions.len();
+        let result = self.run(input);
+        if result.is_err() {
+            self.stack = stack;
+            self.word_map = word_map;
+            self.definitions.truncate(definitions);
+        }
+        result
+    }
+
+    fn run(&mut self, input: &str) -> ForthResult {
+        let v = self.input_parse(input)?;
+        self.loop_stack.clear();
+        self.return_stack.clear();
+
+        let mut calls: Vec<Frame> = Vec::new();
+        let mut word: Option<usize> = None;
+        let mut pc = 0;
+        let mut steps = 0;
+        loop {
+            let item = match word {
+                None => v.get(pc),
+                Some(i) => self.definitions[i].get(pc),
+            }.cloned();
+            let item = match item {
+                Some(item) => item,
+                None => {
+                    // Reached the end of the input or of a called word
+                    let frame = match calls.pop() {
+                        Some(frame) => frame,
+                        None => break,
+                    };
+                    if self.return_stack.len() != frame.return_depth {
+                        return Err(Error::ReturnStackImbalance);
+                    }
+                    word = frame.word;
+                    pc = frame.pc;
+                    continue;
+                },
+            };
+
+            steps += 1;
+            if self.step_limit.is_some_and(|limit| steps > limit) {
+                return Err(Error::StepLimitExceeded);
+            }
+            if let Item::Exec_(s) = item {
+                match s {
+                    Exec::Arith_(o) => {
+                        let top = top_mut(&mut self.stack, 2)?;
+                        top[0] = eval_oper(top[1], top[0], o, self.overflow)?;
+                        self.stack.pop();
+                    },
+                    Exec::Unary_(o) => {
+                        let top = top_mut(&mut self.stack, 1)?;
+                        top[0] = eval_unary(top[0], o, self.overflow)?;
+                    },
+                    Exec::Div_(o) => {
+                        eval_div(&mut self.stack, o, self.overflow)?;
+                    },
+                    Exec::Stack_(c) => {
+                        eval_command(&mut self.stack, c)?;
+                    },
+                    Exec::Value_(v) => {
+                        self.stack.push(v);
+                    },
+                    Exec::Branch_(b) => {
+                        if let Some(offset) = eval_branch(&mut self.stack, &mut self.loop_stack, b)? {
+                            pc = (pc as isize + offset) as usize;
+                            continue;
+                        }
+                    },
+                    Exec::Loop_(w) => {
+                        eval_loop(&mut self.stack, &mut self.loop_stack, w)?;
+                    },
+                    Exec::Return_(w) => {
+                        let base = calls.last().map_or(0, |frame| frame.return_depth);
+                        eval_return(&mut self.stack, &mut self.return_stack, base, w)?;
+                    },
+                    Exec::Call_(i) => {
+                        calls.push(Frame { word, pc: pc + 1, return_depth: self.return_stack.len() });
+                        word = Some(i);
+                        pc = 0;
+                        continue;
+                    },
+                }
+            }
+            pc += 1;
+        }
+
+        if !self.return_stack.is_empty() {
+            return Err(Error::ReturnStackImbalance);
+        }
+        Ok(())
+    }
+
+    fn input_parse(&mut self, input: &str) -> Result<Vec<Item<C>>, Error> {
+        let mut items = Vec::new();
+        let mut state = ParseState::Normal;
+        let mut curr_custom_word = None;
+        let mut body = Vec::new();
+        let mut control = Vec::new();
+
+        let input_uppercased = input.to_uppercase();
+        let input_separated = to_space_separated(&input_uppercased);
+        let input_split = input_separated.split_whitespace();
+
+        for item_str in input_split {
+            match state {
+                ParseState::Normal => {
+                    let v = self.str_to_item(item_str)?;
+
+                    match v.last() {
+                        Some(&Item::Symbol_(Symbol::Colon)) => state = ParseState::CustomInit,
+                        // Control structures are compile-only
+                        Some(&Item::Symbol_(_)) => return Err(Error::InvalidWord),
+                        _ => items.extend(v),
+                    }
+                },
+                ParseState::CustomInit => {
+                    // Cannot re-define numbers
+                    if let Ok(v) = self.str_to_item(item_str) {
+                        let first_item = v.last().ok_or(Error::InvalidWord)?;
+
+                        if let Item::Exec_(Exec::Value_(_)) = *first_item {
+                            return Err(Error::InvalidWord);
+                        }
+                    }
+
+                    let custom_word = item_str.to_owned();
+                    curr_custom_word = Some(custom_word.clone());
+                    self.word_map.insert(custom_word, Vec::new());
+
+                    state = ParseState::Custom;
+                },
+                ParseState::Custom => {
+                    let v = self.str_to_item(item_str)?;
+
+                    match v.last() {
+                        Some(&Item::Symbol_(Symbol::SemiColon)) => {
+                            if !control.is_empty() {
+                                return Err(Error::InvalidWord);
+                            }
+                            let call = Item::Exec_(Exec::Call_(self.definitions.len()));
+                            self.definitions.push(mem::take(&mut body));
+                            self.word_map.insert(curr_custom_word.take().unwrap(), vec![call]);
+                            state = ParseState::Normal;
+                        },
+                        Some(&Item::Symbol_(s)) => compile_control(&mut body, &mut control, s)?,
+                        _ => body.extend(v),
+                    }
+                },
+            }
+        }
+
+        match state {
+            ParseState::Normal => Ok(items),
+            _ => Err(Error::InvalidWord),
+        }
+    }
+
+    fn str_to_item(&self, s: &str) -> Result<Vec<Item<C>>, Error> {
+        match s.parse::<C>() {
+            Ok(v) => Ok(vec![Item::Exec_(Exec::Value_(v))]),
+            Err(_) => self.word_map.get(&s.to_uppercase()).cloned().ok_or(Error::UnknownWord),
+        }
+    }
+}
+
+fn eval_oper<C: Cell>(a: C, b: C, o: ArithWord, overflow: Overflow) -> Result<C, Error> {
+    let zero = C::from(0);
+    match o {
+        ArithWord::Add => overflow.narrow(b.add(a)),
+        ArithWord::Sub => overflow.narrow(b.sub(a)),
+        ArithWord::Mul => overflow.narrow(b.mul(a)),
+        ArithWord::Div => {
+            match a {
+                a if a == zero => Err(Error::DivisionByZero),
+                a => overflow.narrow(b.div(a)),
+            }
+        },
+        // The remainder always fits, even for MIN / -1
+        ArithWord::Mod => {
+            match a {
+                a if a == zero => Err(Error::DivisionByZero),
+                a => Ok(b.wrapping_rem(a)),
+            }
+        },
+        ArithWord::Min => Ok(b.min(a)),
+        ArithWord::Max => Ok(b.max(a)),
+        ArithWord::Eq => Ok(flag(b == a)),
+        ArithWord::Ne => Ok(flag(b != a)),
+        ArithWord::Lt => Ok(flag(b < a)),
+        ArithWord::Gt => Ok(flag(b > a)),
+        ArithWord::ULt => Ok(flag(b.unsigned_lt(a))),
+        ArithWord::And => Ok(b & a),
+        ArithWord::Or => Ok(b | a),
+        ArithWord::Xor => Ok(b ^ a),
+        ArithWord::LShift => Ok(b.lshift(a)),
+        ArithWord::RShift => Ok(b.rshift(a)),
+    }
+}
+
+fn eval_unary<C: Cell>(a: C, o: UnaryWord, overflow: Overflow) -> Result<C, Error> {
+    match o {
+        UnaryWord::Negate => overflow.narrow(a.neg()),
+        UnaryWord::Abs => overflow.narrow(a.abs()),
+        UnaryWord::TwoDiv => Ok(a >> 1),
+    }
+}
+
+// Divides with a double-width intermediate, so neither the dividend of
+// FM/MOD and SM/REM nor the product of */ can overflow.
+fn eval_div<C: Cell>(stack: &mut Vec<C>, o: DivWord, overflow: Overflow) -> ForthResult {
+    let arity = if o == DivWord::DivMod { 2 } else { 3 };
+    let args = top_mut(stack, arity)?.to_vec();
+
+    // Double-cell numbers keep the most significant cell on top of the stack
+    let ((lo, hi), divisor) = match o {
+        DivWord::DivMod => (sign_extend(args[0]), args[1]),
+        DivWord::StarSlash | DivWord::StarSlashMod => (args[0].mul_double(args[1]), args[2]),
+        DivWord::FmMod | DivWord::SmRem => ((args[0], args[1]), args[2]),
+    };
+    if divisor == C::from(0) {
+        return Err(Error::DivisionByZero);
+    }
+
+    let (rem, quot) = C::div_double(lo, hi, divisor, o == DivWord::FmMod);
+    let quot = overflow.narrow(quot)?;
+
+    let len = stack.len();
+    stack.truncate(len - arity);
+    if o != DivWord::StarSlash {
+        stack.push(rem);
+    }
+    stack.push(quot);
+    Ok(())
+}
+
+fn sign_extend<C: Cell>(n: C) -> (C, C) {
+    (n, if n < C::from(0) { C::from(-1) } else { C::from(0) })
+}
+
+// Well-formed flags have either all bits set or none
+fn flag<C: Cell>(b: bool) -> C {
+    C::from(if b { -1 } else { 0 })
+}
+
+fn eval_command<C: Cell>(stack: &mut Vec<C>, c: StackWord) -> ForthResult {
+    match c {
+        StackWord::Dup => {
+            let a = stack.last().cloned().ok_or(Error::StackUnderflow)?;
+            stack.push(a);
+        },
+        StackWord::Drop => {
+            if stack.pop().is_none() {
+                return Err(Error::StackUnderflow);
+            }
+        },
+        StackWord::Swap => top_mut(stack, 2)?.swap(0, 1),
+        StackWord::Over => {
+            let len = stack.len();
+            if len < 2 { return Err(Error::StackUnderflow) };
+            let a = stack.get(len - 2).cloned().ok_or(Error::StackUnderflow)?;
+            stack.push(a);
+        },
+        StackWord::Rot => top_mut(stack, 3)?.rotate_left(1),
+        StackWord::MinusRot => top_mut(stack, 3)?.rotate_right(1),
+        StackWord::Nip => {
+            top_mut(stack, 2)?;
+            let len = stack.len();
+            stack.remove(len - 2);
+        },
+        StackWord::Tuck => {
+            let a = top_mut(stack, 2)?[1];
+            let len = stack.len();
+            stack.insert(len - 2, a);
+        },
+        StackWord::Pick => {
+            let u = pick_index(stack)?;
+            let len = stack.len();
+            stack[len - 1] = stack[len - 2 - u];
+        },
+        StackWord::Roll => {
+            let u = pick_index(stack)?;
+            stack.pop();
+            top_mut(stack, u + 1)?.rotate_left(1);
+        },
+        StackWord::QDup => {
+            let a = top_mut(stack, 1)?[0];
+            if a != C::from(0) {
+                stack.push(a);
+            }
+        },
+        StackWord::Depth => {
+            let len = stack.len();
+            stack.push(C::from_usize(len));
+        },
+        StackWord::TwoDup => {
+            top_mut(stack, 2)?;
+            let len = stack.len();
+            stack.extend_from_within(len - 2..);
+        },
+        StackWord::TwoDrop => {
+            top_mut(stack, 2)?;
+            let len = stack.len();
+            stack.truncate(len - 2);
+        },
+        StackWord::TwoSwap => top_mut(stack, 4)?.rotate_left(2),
+        StackWord::TwoOver => {
+            top_mut(stack, 4)?;
+            let len = stack.len();
+            stack.extend_from_within(len - 4..len - 2);
+        },
+        StackWord::TwoRot => top_mut(stack, 6)?.rotate_left(2),
+    }
+    Ok(())
+}
+
+// The top `n` cells of the stack, deepest first
+fn top_mut<C>(stack: &mut [C], n: usize) -> Result<&mut [C], Error> {
+    let len = stack.len();
+    if len < n { return Err(Error::StackUnderflow) };
+    Ok(&mut stack[len - n..])
+}
+
+// Pops the top two cells, topmost first, or neither of them
+fn pop_pair<C: Cell>(stack: &mut Vec<C>) -> Result<(C, C), Error> {
+    let (b, a) = {
+        let top = top_mut(stack, 2)?;
+        (top[0], top[1])
+    };
+    let len = stack.len();
+    stack.truncate(len - 2);
+    Ok((a, b))
+}
+
+// Validates the index on top of the stack against the cells beneath it
+fn pick_index<C: Cell>(stack: &[C]) -> Result<usize, Error> {
+    let u = stack.last().ok_or(Error::StackUnderflow)?;
+    u.to_usize().filter(|&u| u < stack.len() - 1).ok_or(Error::StackUnderflow)
+}
+
+fn eval_branch<C: Cell>(stack: &mut Vec<C>, loop_stack: &mut Vec<LoopFrame<C>>, b: Branch) -> Result<Option<isize>, Error> {
+    match b {
+        Branch::Always(offset) => Ok(Some(offset)),
+        Branch::IfZero(offset) => {
+            let flag = stack.pop().ok_or(Error::StackUnderflow)?;
+            Ok(if flag == C::from(0) { Some(offset) } else { None })
+        },
+        Branch::QDo(offset) => {
+            let (index, limit) = pop_pair(stack)?;
+            if index == limit {
+                return Ok(Some(offset));
+            }
+            loop_stack.push(LoopFrame { index, limit });
+            Ok(None)
+        },
+        Branch::Loop(offset) => loop_step(loop_stack, C::from(1), offset),
+        Branch::PlusLoop(offset) => {
+            let step = stack.pop().ok_or(Error::StackUnderflow)?;
+            loop_step(loop_stack, step, offset)
+        },
+        Branch::Leave(offset) => {
+            loop_stack.pop().ok_or(Error::StackUnderflow)?;
+            Ok(Some(offset))
+        },
+    }
+}
+
+// Advances the innermost loop, branching back to its body until the index
+// crosses the boundary between limit - 1 and limit in either direction.
+fn loop_step<C: Cell>(loop_stack: &mut Vec<LoopFrame<C>>, step: C, offset: isize) -> Result<Option<isize>, Error> {
+    let frame = loop_stack.last_mut().ok_or(Error::StackUnderflow)?;
+    let old_diff = frame.index.wrapping_sub(frame.limit);
+    let new_diff = old_diff.wrapping_add(step);
+    frame.index = frame.index.wrapping_add(step);
+
+    if (old_diff ^ new_diff) & (old_diff ^ step) < C::from(0) {
+        loop_stack.pop();
+        Ok(None)
+    } else {
+        Ok(Some(offset))
+    }
+}
+
+fn eval_loop<C: Cell>(stack: &mut Vec<C>, loop_stack: &mut Vec<LoopFrame<C>>, w: LoopWord) -> ForthResult {
+    match w {
+        LoopWord::Do => {
+            let (index, limit) = pop_pair(stack)?;
+            loop_stack.push(LoopFrame { index, limit });
+        },
+        LoopWord::I => {
+            let frame = loop_stack.last().ok_or(Error::StackUnderflow)?;
+            stack.push(frame.index);
+        },
+        LoopWord::J => {
+            let len = loop_stack.len();
+            if len < 2 { return Err(Error::StackUnderflow) };
+            stack.push(loop_stack[len - 2].index);
+        },
+        LoopWord::Unloop => {
+            loop_stack.pop().ok_or(Error::StackUnderflow)?;
+        },
+    }
+    Ok(())
+}
+
+// Words may only take from the return stack what they put there themselves
+fn eval_return<C: Cell>(stack: &mut Vec<C>, return_stack: &mut Vec<C>, base: usize, w: ReturnWord) -> ForthResult {
+    let available = return_stack.len() - base;
+    match w {
+        ReturnWord::ToR | ReturnWord::TwoToR => {
+            let n = if w == ReturnWord::ToR { 1 } else { 2 };
+            top_mut(stack, n)?;
+            let len = stack.len();
+            return_stack.extend(stack.drain(len - n..));
+        },
+        ReturnWord::FromR | ReturnWord::TwoFromR => {
+            let n = if w == ReturnWord::FromR { 1 } else { 2 };
+            if available < n { return Err(Error::ReturnStackImbalance) };
+            let len = return_stack.len();
+            stack.extend(return_stack.drain(len - n..));
+        },
+        ReturnWord::Fetch => {
+            let a = match available {
+                0 => return Err(Error::ReturnStackImbalance),
+                _ => return_stack[return_stack.len() - 1],
+            };
+            stack.push(a);
+        },
+    }
+    Ok(())
+}
+
+// Compiles a control-flow word into the body of the word being defined
+fn compile_control<C: Cell>(body: &mut Vec<Item<C>>, control: &mut Vec<Control>, s: Symbol) -> ForthResult {
+    match s {
+        Symbol::If => {
+            control.push(Control::If(body.len()));
+            body.push(Item::Exec_(Exec::Branch_(Branch::IfZero(0))));
+        },
+        Symbol::Else => {
+            let orig = match control.pop() {
+                Some(Control::If(orig)) => orig,
+                _ => return Err(Error::InvalidWord),
+            };
+            control.push(Control::Else(body.len()));
+            body.push(Item::Exec_(Exec::Branch_(Branch::Always(0))));
+            resolve_branch(body, orig);
+        },
+        Symbol::Then => {
+            match control.pop() {
+                Some(Control::If(orig)) | Some(Control::Else(orig)) |
+                Some(Control::While(orig)) => resolve_branch(body, orig),
+                _ => return Err(Error::InvalidWord),
+            }
+        },
+        Symbol::Do => {
+            control.push(Control::Do(body.len(), Vec::new()));
+            body.push(Item::Exec_(Exec::Loop_(LoopWord::Do)));
+        },
+        Symbol::QDo => {
+            control.push(Control::Do(body.len(), Vec::new()));
+            body.push(Item::Exec_(Exec::Branch_(Branch::QDo(0))));
+        },
+        Symbol::Loop | Symbol::PlusLoop => {
+            let (dest, leaves) = match control.pop() {
+                Some(Control::Do(dest, leaves)) => (dest, leaves),
+                _ => return Err(Error::InvalidWord),
+            };
+            let offset = dest as isize + 1 - body.len() as isize;
+            let b = if s == Symbol::Loop { Branch::Loop(offset) } else { Branch::PlusLoop(offset) };
+            body.push(Item::Exec_(Exec::Branch_(b)));
+
+            if let Item::Exec_(Exec::Branch_(Branch::QDo(_))) = body[dest] {
+                resolve_branch(body, dest);
+            }
+            for orig in leaves {
+                resolve_branch(body, orig);
+            }
+        },
+        Symbol::Leave => {
+            let leaves = control.iter_mut().rev().filter_map(|c| match *c {
+                Control::Do(_, ref mut leaves) => Some(leaves),
+                _ => None,
+            }).next();
+            match leaves {
+                Some(leaves) => leaves.push(body.len()),
+                None => return Err(Error::InvalidWord),
+            }
+            body.push(Item::Exec_(Exec::Branch_(Branch::Leave(0))));
+        },
+        Symbol::Begin => control.push(Control::Begin(body.len())),
+        Symbol::Until | Symbol::Again => {
+            let dest = match control.pop() {
+                Some(Control::Begin(dest)) => dest,
+                _ => return Err(Error::InvalidWord),
+            };
+            let offset = dest as isize - body.len() as isize;
+            let b = if s == Symbol::Until { Branch::IfZero(offset) } else { Branch::Always(offset) };
+            body.push(Item::Exec_(Exec::Branch_(b)));
+        },
+        Symbol::While => {
+            let dest = match control.pop() {
+                Some(Control::Begin(dest)) => dest,
+                _ => return Err(Error::InvalidWord),
+            };
+            control.push(Control::While(body.len()));
+            control.push(Control::Begin(dest));
+            body.push(Item::Exec_(Exec::Branch_(Branch::IfZero(0))));
+        },
+        Symbol::Repeat => {
+            let dest = match control.pop() {
+                Some(Control::Begin(dest)) => dest,
+                _ => return Err(Error::InvalidWord),
+            };
+            let orig = match control.pop() {
+                Some(Control::While(orig)) => orig,
+                _ => return Err(Error::InvalidWord),
+            };
+            let offset = dest as isize - body.len() as isize;
+            body.push(Item::Exec_(Exec::Branch_(Branch::Always(offset))));
+            resolve_branch(body, orig);
+        },
+        Symbol::Colon | Symbol::SemiColon => return Err(Error::InvalidWord),
+    }
+    Ok(())
+}
+
+// Points the forward branch at `orig` to the end of `body`
+fn resolve_branch<C: Cell>(body: &mut [Item<C>], orig: usize) {
+    if let Item::Exec_(Exec::Branch_(b)) = body[orig] {
+        let offset = (body.len() - orig) as isize;
+        body[orig] = Item::Exec_(Exec::Branch_(b.with_offset(offset)));
+    }
+}
+
+fn to_space_separated(s: &str) -> String {
+    s.chars().map(|c| if c.is_control() { ' ' } else { c }).collect()
+}
+
+struct StackFormat<'a, C: Cell + 'a>(&'a Forth<C>);
+
+impl<'a, C: Cell> fmt::Display for StackFormat<'a, C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some((head, tail)) = self.0.stack.split_first() {
+            write!(f, "{}", head)?;
+
+            for v in tail {
+                write!(f, " {}", v)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,281 @@
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
+use std::mem;
+use std::ops::{BitAnd, BitOr, BitXor, Shr};
+use std::str::FromStr;
+
+/// Outcome of an arithmetic operation whose exact result may not fit in a cell
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct Overflowing<C> {
+    pub wrapped: C,
+    pub saturated: C,
+    pub overflowed: bool,
+}
+
+/// A signed integer that can be used as the cell type of `Forth`.
+///
+/// Implemented for `i16`, `i32`, `i64` and `i128`.
+pub trait Cell: Copy + Ord + fmt::Debug + fmt::Display + FromStr + From<i8>
+    + BitAnd<Output = Self> + BitOr<Output = Self> + BitXor<Output = Self> + Shr<u32, Output = Self>
+    + private::Sealed
+{
+    const MIN: Self;
+    const MAX: Self;
+    // Size in the data space
+    const BYTES: usize;
+
+    fn add(self, rhs: Self) -> Overflowing<Self>;
+    fn sub(self, rhs: Self) -> Overflowing<Self>;
+    fn mul(self, rhs: Self) -> Overflowing<Self>;
+    // `rhs` must not be zero
+    fn div(self, rhs: Self) -> Overflowing<Self>;
+    fn neg(self) -> Overflowing<Self>;
+    fn abs(self) -> Overflowing<Self>;
+
+    fn wrapping_add(self, rhs: Self) -> Self;
+    fn wrapping_sub(self, rhs: Self) -> Self;
+    // `rhs` must not be zero
+    fn wrapping_rem(self, rhs: Self) -> Self;
+
+    fn unsigned_lt(self, rhs: Self) -> bool;
+    // Logical shifts; shifting out every bit leaves zero
+    fn lshift(self, n: Self) -> Self;
+    fn rshift(self, n: Self) -> Self;
+
+    // None if negative or too large to index with
+    fn to_usize(self) -> Option<usize>;
+    // Keeps the low bits of `n`
+    fn from_usize(n: usize) -> Self;
+    // None if out of range
+    fn to_i32(self) -> Option<i32>;
+    // Keeps the low bits of `n`
+    fn from_i32(n: i32) -> Self;
+    fn low_byte(self) -> u8;
+    // The same bits read as an unsigned number
+    fn unsigned(self) -> u128;
+
+    // Native byte order; `bytes` must be exactly `BYTES` long
+    fn load(bytes: &[u8]) -> Self;
+    fn store(self, bytes: &mut [u8]);
+
+    // Exact product as a double cell, least significant cell first
+    fn mul_double(self, rhs: Self) -> (Self, Self);
+    // Divides the double cell `lo hi` by a non-zero `divisor`, rounding the
+    // quotient towards negative infinity if `floored` and towards zero
+    // otherwise. Returns the remainder, which always fits, and the quotient.
+    fn div_double(lo: Self, hi: Self, divisor: Self, floored: bool) -> (Self, Overflowing<Self>);
+}
+
+mod private {
+    pub trait Sealed {}
+}
+
+macro_rules! overflowing {
+    ($a:expr, $wrapping:ident, $checked:ident, $saturating:ident $(, $b:expr)*) => {
+        Overflowing {
+            wrapped: $a.$wrapping($($b),*),
+            saturated: $a.$saturating($($b),*),
+            overflowed: $a.$checked($($b),*).is_none(),
+        }
+    };
+}
+
+macro_rules! cell_impl {
+    // Double-cell arithmetic through a native integer of twice the width
+    ($t:ident, $u:ident, $d:ident) => {
+        cell_impl!($t, $u, {
+            fn mul_double(self, rhs: $t) -> ($t, $t) {
+                let p = self as $d * rhs as $d;
+                (p as $t, (p >> <$t>::BITS) as $t)
+            }
+
+            fn div_double(lo: $t, hi: $t, divisor: $t, floored: bool) -> ($t, Overflowing<$t>) {
+                let dividend = (hi as $d) << <$t>::BITS | lo as $u as $d;
+                let divisor = divisor as $d;
+                let (mut quot, quot_overflowed) = dividend.overflowing_div(divisor);
+                let mut rem = dividend.wrapping_rem(divisor);
+                if floored && rem != 0 && (rem < 0) != (divisor < 0) {
+                    quot -= 1;
+                    rem += divisor;
+                }
+
+                let quot = if quot_overflowed {
+                    // Only the most negative double divided by -1 gets here;
+                    // the exact quotient is a power of two whose low cell is zero
+                    Overflowing { wrapped: 0, saturated: <$t>::MAX, overflowed: true }
+                } else {
+                    Overflowing {
+                        wrapped: quot as $t,
+                        saturated: quot.max(<$t>::MIN as $d).min(<$t>::MAX as $d) as $t,
+                        overflowed: quot < <$t>::MIN as $d || quot > <$t>::MAX as $d,
+                    }
+                };
+                (rem as $t, quot)
+            }
+        });
+    };
+    ($t:ident, $u:ident, { $($double:tt)* }) => {
+        impl private::Sealed for $t {}
+
+        impl Cell for $t {
+            const MIN: $t = <$t>::MIN;
+            const MAX: $t = <$t>::MAX;
+            const BYTES: usize = mem::size_of::<$t>();
+
+            fn add(self, rhs: $t) -> Overflowing<$t> {
+                overflowing!(self, wrapping_add, checked_add, saturating_add, rhs)
+            }
+
+            fn sub(self, rhs: $t) -> Overflowing<$t> {
+                overflowing!(self, wrapping_sub, checked_sub, saturating_sub, rhs)
+            }
+
+            fn mul(self, rhs: $t) -> Overflowing<$t> {
+                overflowing!(self, wrapping_mul, checked_mul, saturating_mul, rhs)
+            }
+
+            fn div(self, rhs: $t) -> Overflowing<$t> {
+                overflowing!(self, wrapping_div, checked_div, saturating_div, rhs)
+            }
+
+            fn neg(self) -> Overflowing<$t> {
+                overflowing!(self, wrapping_neg, checked_neg, saturating_neg)
+            }
+
+            fn abs(self) -> Overflowing<$t> {
+                overflowing!(self, wrapping_abs, checked_abs, saturating_abs)
+            }
+
+            fn wrapping_add(self, rhs: $t) -> $t {
+                <$t>::wrapping_add(self, rhs)
+            }
+
+            fn wrapping_sub(self, rhs: $t) -> $t {
+                <$t>::wrapping_sub(self, rhs)
+            }
+
+            fn wrapping_rem(self, rhs: $t) -> $t {
+                <$t>::wrapping_rem(self, rhs)
+            }
+
+            fn unsigned_lt(self, rhs: $t) -> bool {
+                (self as $u) < (rhs as $u)
+            }
+
+            fn lshift(self, n: $t) -> $t {
+                if n < 0 || n >= <$t>::BITS as $t { 0 } else { ((self as $u) << n) as $t }
+            }
+
+            fn rshift(self, n: $t) -> $t {
+                if n < 0 || n >= <$t>::BITS as $t { 0 } else { ((self as $u) >> n) as $t }
+            }
+
+            fn to_usize(self) -> Option<usize> {
+                usize::try_from(self).ok()
+            }
+
+            fn from_usize(n: usize) -> $t {
+                n as $t
+            }
+
+            fn to_i32(self) -> Option<i32> {
+                i32::try_from(self).ok()
+            }
+
+            fn from_i32(n: i32) -> $t {
+                n as $t
+            }
+
+            fn low_byte(self) -> u8 {
+                self as u8
+            }
+
+            fn unsigned(self) -> u128 {
+                self as $u as u128
+            }
+
+            fn load(bytes: &[u8]) -> $t {
+                <$t>::from_ne_bytes(bytes.try_into().unwrap())
+            }
+
+            fn store(self, bytes: &mut [u8]) {
+                bytes.copy_from_slice(&self.to_ne_bytes())
+            }
+
+            $($double)*
+        }
+    };
+}
+
+cell_impl!(i16, u16, i32);
+cell_impl!(i32, u32, i64);
+cell_impl!(i64, u64, i128);
+cell_impl!(i128, u128, {
+    fn mul_double(self, rhs: i128) -> (i128, i128) {
+        let (lo, hi) = mul_u128(self.unsigned_abs(), rhs.unsigned_abs());
+        let (lo, hi) = if (self < 0) != (rhs < 0) { neg_u256(lo, hi) } else { (lo, hi) };
+        (lo as i128, hi as i128)
+    }
+
+    fn div_double(lo: i128, hi: i128, divisor: i128, floored: bool) -> (i128, Overflowing<i128>) {
+        // Sign-magnitude long division, as there is no native 256-bit integer
+        let negative = hi < 0;
+        let (lo, hi) = if negative { neg_u256(lo as u128, hi as u128) } else { (lo as u128, hi as u128) };
+        let d = divisor.unsigned_abs();
+
+        let (mut quot_lo, mut quot_hi, mut rem) = (0u128, 0u128, 0u128);
+        for i in (0..256).rev() {
+            let bit = if i >= 128 { (hi >> (i - 128)) & 1 } else { (lo >> i) & 1 };
+            let carry = rem >> 127;
+            rem = rem << 1 | bit;
+            if carry == 1 || rem >= d {
+                rem = rem.wrapping_sub(d);
+                if i >= 128 { quot_hi |= 1 << (i - 128) } else { quot_lo |= 1 << i }
+            }
+        }
+
+        // The remainder takes the sign of the dividend
+        let quot_negative = negative != (divisor < 0);
+        let mut rem = if negative { (rem as i128).wrapping_neg() } else { rem as i128 };
+        if floored && rem != 0 && quot_negative {
+            let (l, c) = quot_lo.overflowing_add(1);
+            quot_lo = l;
+            quot_hi += c as u128;
+            rem += divisor;
+        }
+
+        let limit = if quot_negative { i128::MIN.unsigned_abs() } else { i128::MAX as u128 };
+        let overflowed = quot_hi != 0 || quot_lo > limit;
+        let wrapped = if quot_negative { quot_lo.wrapping_neg() as i128 } else { quot_lo as i128 };
+        let saturated = match (overflowed, quot_negative) {
+            (false, _) => wrapped,
+            (true, true) => i128::MIN,
+            (true, false) => i128::MAX,
+        };
+        (rem, Overflowing { wrapped, saturated, overflowed })
+    }
+});
+
+// Full 256-bit product of two 128-bit integers, least significant half first
+fn mul_u128(a: u128, b: u128) -> (u128, u128) {
+    const MASK: u128 = u64::MAX as u128;
+    let (a0, a1) = (a & MASK, a >> 64);
+    let (b0, b1) = (b & MASK, b >> 64);
+
+    let p00 = a0 * b0;
+    let p01 = a0 * b1;
+    let p10 = a1 * b0;
+    let p11 = a1 * b1;
+
+    let mid = (p00 >> 64) + (p01 & MASK) + (p10 & MASK);
+    let lo = (p00 & MASK) | mid << 64;
+    let hi = p11 + (p01 >> 64) + (p10 >> 64) + (mid >> 64);
+    (lo, hi)
+}
+
+// Two's complement negation of a 256-bit integer
+fn neg_u256(lo: u128, hi: u128) -> (u128, u128) {
+    let lo = (!lo).wrapping_add(1);
+    let hi = (!hi).wrapping_add((lo == 0) as u128);
+    (lo, hi)
+}
@@ -1,228 +1,1016 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
+use std::io::{self, Write};
+use std::mem;
+use std::ops::Range;
 
+mod cell;
+mod source;
+mod vm;
+
+pub use cell::{Cell, Overflowing};
+use source::Source;
+use vm::{Catch, Frame, LoopFrame, Op};
+
+// Cell type of `Forth::new()`
 pub type Value = i32;
 pub type ForthResult = Result<(), Error>;
 
-#[derive(Debug, PartialEq, Copy, Clone)]
-enum ArithWord { Add, Sub, Mul, Div }
-
-#[derive(Debug, PartialEq, Copy, Clone)]
-enum StackWord { Dup, Drop, Swap, Over }
+// How deeply words may call each other unless `set_max_call_depth` is used
+const DEFAULT_MAX_CALL_DEPTH: usize = 1024;
+// Size in bytes the data space may grow to unless `set_max_data_space` is used
+const DEFAULT_MAX_DATA_SPACE: usize = 64 * 1024;
+// Words kept at each end of the call chain of an error
+const ERROR_CALLS_KEPT: usize = 8;
 
 #[derive(Debug, PartialEq, Copy, Clone)]
-enum Symbol { Colon, SemiColon }
+enum Symbol {
+    Colon, SemiColon,
+    If, Else, Then,
+    Do, QDo, Loop, PlusLoop, Leave,
+    Begin, Until, While, Repeat, Again,
+    Recurse, To, Does,
+    DotQuote, SQuote, CQuote, DotParen,
+    Paren, Backslash,
+    AbortQuote, BracketTick,
+}
 
-#[derive(Debug, PartialEq, Copy, Clone)]
-enum Item {
-    Exec_(Exec),
+// What a word in the dictionary means to the compiler
+#[derive(Debug, PartialEq, Clone)]
+enum Item<C> {
+    Code_(Vec<Op<C>>),  // Instructions compiled in place of the word
     Symbol_(Symbol),
 }
 
-#[derive(Debug, PartialEq, Copy, Clone)]
-enum Exec {
-    Arith_(ArithWord),
-    Stack_(StackWord),
-    Value_(Value),
+// Entry of a word in the dictionary
+#[derive(Debug, PartialEq, Clone)]
+struct Word<C> {
+    item: Item<C>,
+    // Execution token: address of code that performs the word on its own.
+    // Words made by CREATE have a two-instruction stub here that pushes
+    // their data field address and then either exits or jumps to the code
+    // after DOES>. Zero for compiler words.
+    xt: usize,
 }
 
-fn default_word_map() -> HashMap<String, Vec<Item>> {
+fn default_word_map<C: Cell>() -> HashMap<String, Item<C>> {
     let mut m = HashMap::new();
-    m.insert("DUP".to_owned(),  vec![Item::Exec_(Exec::Stack_(StackWord::Dup))]);
-    m.insert("DROP".to_owned(), vec![Item::Exec_(Exec::Stack_(StackWord::Drop))]);
-    m.insert("SWAP".to_owned(), vec![Item::Exec_(Exec::Stack_(StackWord::Swap))]);
-    m.insert("OVER".to_owned(), vec![Item::Exec_(Exec::Stack_(StackWord::Over))]);
-    m.insert("+".to_owned(),    vec![Item::Exec_(Exec::Arith_(ArithWord::Add))]);
-    m.insert("-".to_owned(),    vec![Item::Exec_(Exec::Arith_(ArithWord::Sub))]);
-    m.insert("*".to_owned(),    vec![Item::Exec_(Exec::Arith_(ArithWord::Mul))]);
-    m.insert("/".to_owned(),    vec![Item::Exec_(Exec::Arith_(ArithWord::Div))]);
-    m.insert(":".to_owned(),    vec![Item::Symbol_(Symbol::Colon)]);
-    m.insert(";".to_owned(),    vec![Item::Symbol_(Symbol::SemiColon)]);
+    m.insert("DUP".to_owned(),  Item::Code_(vec![Op::Dup]));
+    m.insert("DROP".to_owned(), Item::Code_(vec![Op::Drop]));
+    m.insert("SWAP".to_owned(), Item::Code_(vec![Op::Swap]));
+    m.insert("OVER".to_owned(), Item::Code_(vec![Op::Over]));
+    m.insert("ROT".to_owned(),  Item::Code_(vec![Op::Rot]));
+    m.insert("-ROT".to_owned(), Item::Code_(vec![Op::MinusRot]));
+    m.insert("NIP".to_owned(),  Item::Code_(vec![Op::Nip]));
+    m.insert("TUCK".to_owned(), Item::Code_(vec![Op::Tuck]));
+    m.insert("PICK".to_owned(), Item::Code_(vec![Op::Pick]));
+    m.insert("ROLL".to_owned(), Item::Code_(vec![Op::Roll]));
+    m.insert("?DUP".to_owned(), Item::Code_(vec![Op::QDup]));
+    m.insert("DEPTH".to_owned(), Item::Code_(vec![Op::Depth]));
+    m.insert("2DUP".to_owned(), Item::Code_(vec![Op::TwoDup]));
+    m.insert("2DROP".to_owned(), Item::Code_(vec![Op::TwoDrop]));
+    m.insert("2SWAP".to_owned(), Item::Code_(vec![Op::TwoSwap]));
+    m.insert("2OVER".to_owned(), Item::Code_(vec![Op::TwoOver]));
+    m.insert("2ROT".to_owned(), Item::Code_(vec![Op::TwoRot]));
+    m.insert("+".to_owned(),    Item::Code_(vec![Op::Add]));
+    m.insert("-".to_owned(),    Item::Code_(vec![Op::Sub]));
+    m.insert("*".to_owned(),    Item::Code_(vec![Op::Mul]));
+    m.insert("/".to_owned(),    Item::Code_(vec![Op::Div]));
+    m.insert("MOD".to_owned(),  Item::Code_(vec![Op::Mod]));
+    m.insert("/MOD".to_owned(), Item::Code_(vec![Op::DivMod]));
+    m.insert("*/".to_owned(),   Item::Code_(vec![Op::StarSlash]));
+    m.insert("*/MOD".to_owned(), Item::Code_(vec![Op::StarSlashMod]));
+    m.insert("FM/MOD".to_owned(), Item::Code_(vec![Op::FmMod]));
+    m.insert("SM/REM".to_owned(), Item::Code_(vec![Op::SmRem]));
+    m.insert("NEGATE".to_owned(), Item::Code_(vec![Op::Negate]));
+    m.insert("ABS".to_owned(),  Item::Code_(vec![Op::Abs]));
+    m.insert("MIN".to_owned(),  Item::Code_(vec![Op::Min]));
+    m.insert("MAX".to_owned(),  Item::Code_(vec![Op::Max]));
+    m.insert("1+".to_owned(),   Item::Code_(vec![Op::OnePlus]));
+    m.insert("1-".to_owned(),   Item::Code_(vec![Op::OneMinus]));
+    m.insert("2*".to_owned(),   Item::Code_(vec![Op::TwoMul]));
+    m.insert("2/".to_owned(),   Item::Code_(vec![Op::TwoDiv]));
+    m.insert("=".to_owned(),    Item::Code_(vec![Op::Eq]));
+    m.insert("<>".to_owned(),   Item::Code_(vec![Op::Ne]));
+    m.insert("<".to_owned(),    Item::Code_(vec![Op::Lt]));
+    m.insert(">".to_owned(),    Item::Code_(vec![Op::Gt]));
+    m.insert("U<".to_owned(),   Item::Code_(vec![Op::ULt]));
+    m.insert("0=".to_owned(),   Item::Code_(vec![Op::ZeroEq]));
+    m.insert("0<".to_owned(),   Item::Code_(vec![Op::ZeroLt]));
+    m.insert("0>".to_owned(),   Item::Code_(vec![Op::ZeroGt]));
+    m.insert("TRUE".to_owned(), Item::Code_(vec![Op::Lit(C::from(-1))]));
+    m.insert("FALSE".to_owned(), Item::Code_(vec![Op::Lit(C::from(0))]));
+    m.insert("AND".to_owned(),  Item::Code_(vec![Op::And]));
+    m.insert("OR".to_owned(),   Item::Code_(vec![Op::Or]));
+    m.insert("XOR".to_owned(),  Item::Code_(vec![Op::Xor]));
+    m.insert("INVERT".to_owned(), Item::Code_(vec![Op::Invert]));
+    m.insert("LSHIFT".to_owned(), Item::Code_(vec![Op::LShift]));
+    m.insert("RSHIFT".to_owned(), Item::Code_(vec![Op::RShift]));
+    m.insert(":".to_owned(),    Item::Symbol_(Symbol::Colon));
+    m.insert(";".to_owned(),    Item::Symbol_(Symbol::SemiColon));
+    m.insert("IF".to_owned(),   Item::Symbol_(Symbol::If));
+    m.insert("ELSE".to_owned(), Item::Symbol_(Symbol::Else));
+    m.insert("THEN".to_owned(), Item::Symbol_(Symbol::Then));
+    m.insert("DO".to_owned(),   Item::Symbol_(Symbol::Do));
+    m.insert("?DO".to_owned(),  Item::Symbol_(Symbol::QDo));
+    m.insert("LOOP".to_owned(), Item::Symbol_(Symbol::Loop));
+    m.insert("+LOOP".to_owned(), Item::Symbol_(Symbol::PlusLoop));
+    m.insert("LEAVE".to_owned(), Item::Symbol_(Symbol::Leave));
+    m.insert("I".to_owned(),    Item::Code_(vec![Op::I]));
+    m.insert("J".to_owned(),    Item::Code_(vec![Op::J]));
+    m.insert("UNLOOP".to_owned(), Item::Code_(vec![Op::Unloop]));
+    m.insert("BEGIN".to_owned(), Item::Symbol_(Symbol::Begin));
+    m.insert("UNTIL".to_owned(), Item::Symbol_(Symbol::Until));
+    m.insert("WHILE".to_owned(), Item::Symbol_(Symbol::While));
+    m.insert("REPEAT".to_owned(), Item::Symbol_(Symbol::Repeat));
+    m.insert("AGAIN".to_owned(), Item::Symbol_(Symbol::Again));
+    m.insert("RECURSE".to_owned(), Item::Symbol_(Symbol::Recurse));
+    m.insert("EXIT".to_owned(), Item::Code_(vec![Op::Exit]));
+    m.insert(">R".to_owned(),   Item::Code_(vec![Op::ToR]));
+    m.insert("R>".to_owned(),   Item::Code_(vec![Op::FromR]));
+    m.insert("R@".to_owned(),   Item::Code_(vec![Op::RFetch]));
+    m.insert("2>R".to_owned(),  Item::Code_(vec![Op::TwoToR]));
+    m.insert("2R>".to_owned(),  Item::Code_(vec![Op::TwoFromR]));
+    m.insert("@".to_owned(),    Item::Code_(vec![Op::Fetch]));
+    m.insert("!".to_owned(),    Item::Code_(vec![Op::Store]));
+    m.insert("+!".to_owned(),   Item::Code_(vec![Op::PlusStore]));
+    m.insert("VARIABLE".to_owned(), Item::Code_(vec![Op::Variable]));
+    m.insert("CONSTANT".to_owned(), Item::Code_(vec![Op::Constant]));
+    m.insert("VALUE".to_owned(), Item::Code_(vec![Op::Value]));
+    m.insert("TO".to_owned(),   Item::Symbol_(Symbol::To));
+    m.insert("CREATE".to_owned(), Item::Code_(vec![Op::Create]));
+    m.insert("DOES>".to_owned(), Item::Symbol_(Symbol::Does));
+    m.insert(">BODY".to_owned(), Item::Code_(vec![Op::ToBody]));
+    m.insert("'".to_owned(),    Item::Code_(vec![Op::Tick]));
+    m.insert("[']".to_owned(),  Item::Symbol_(Symbol::BracketTick));
+    m.insert("EXECUTE".to_owned(), Item::Code_(vec![Op::Execute]));
+    m.insert("EMIT".to_owned(), Item::Code_(vec![Op::Emit]));
+    m.insert(".".to_owned(),    Item::Code_(vec![Op::Dot]));
+    m.insert("U.".to_owned(),   Item::Code_(vec![Op::UDot]));
+    m.insert(".R".to_owned(),   Item::Code_(vec![Op::DotR]));
+    m.insert("CR".to_owned(),   Item::Code_(vec![Op::Cr]));
+    m.insert("SPACE".to_owned(), Item::Code_(vec![Op::Space]));
+    m.insert("SPACES".to_owned(), Item::Code_(vec![Op::Spaces]));
+    m.insert("TYPE".to_owned(), Item::Code_(vec![Op::Type]));
+    m.insert(".\"".to_owned(),   Item::Symbol_(Symbol::DotQuote));
+    m.insert("S\"".to_owned(),   Item::Symbol_(Symbol::SQuote));
+    m.insert("C\"".to_owned(),   Item::Symbol_(Symbol::CQuote));
+    m.insert(".(".to_owned(),   Item::Symbol_(Symbol::DotParen));
+    m.insert("(".to_owned(),    Item::Symbol_(Symbol::Paren));
+    m.insert("\\".to_owned(),   Item::Symbol_(Symbol::Backslash));
+    m.insert("CATCH".to_owned(), Item::Code_(vec![Op::Catch]));
+    m.insert("THROW".to_owned(), Item::Code_(vec![Op::Throw]));
+    m.insert("ABORT".to_owned(), Item::Code_(vec![Op::Abort]));
+    m.insert("ABORT\"".to_owned(), Item::Symbol_(Symbol::AbortQuote));
+    m.insert("HERE".to_owned(), Item::Code_(vec![Op::Here]));
+    m.insert("ALLOT".to_owned(), Item::Code_(vec![Op::Allot]));
+    m.insert(",".to_owned(),    Item::Code_(vec![Op::Comma]));
+    m.insert("C,".to_owned(),   Item::Code_(vec![Op::CComma]));
+    m.insert("C@".to_owned(),   Item::Code_(vec![Op::CFetch]));
+    m.insert("C!".to_owned(),   Item::Code_(vec![Op::CStore]));
+    m.insert("CELLS".to_owned(), Item::Code_(vec![Op::Cells]));
+    m.insert("CELL+".to_owned(), Item::Code_(vec![Op::CellPlus]));
+    m.insert("CHARS".to_owned(), Item::Code_(vec![]));
+    m.insert("ALIGN".to_owned(), Item::Code_(vec![Op::Align]));
+    m.insert("ALIGNED".to_owned(), Item::Code_(vec![Op::Aligned]));
+    m.insert("FILL".to_owned(), Item::Code_(vec![Op::Fill]));
+    m.insert("ERASE".to_owned(), Item::Code_(vec![Op::Erase]));
+    m.insert("MOVE".to_owned(), Item::Code_(vec![Op::Move]));
     m
 }
 
-pub struct Forth {
-    word_map: HashMap<String, Vec<Item>>,
-    stack: Vec<Value>,
+// User-defined words compile to a call of their body in `code`, so later
+// redefinitions of a name do not affect words already using it.
+pub struct Forth<C: Cell = Value> {
+    word_map: HashMap<String, Word<C>>,
+    // Starts with an `Exit`, which returns to the interpreter, and the
+    // `EndCatch` at `vm::END_CATCH`
+    code: Vec<Op<C>>,
+    latest: Option<usize>,  // Stub of the word made by the last CREATE
+    stubs: HashSet<usize>,  // Stubs of all words made by CREATE, for >BODY
+    patches: Vec<(usize, Op<C>)>,   // Stubs changed by DOES> in this eval, with their old code
+    // Names of words by execution token, including those since redefined.
+    // Only these may be executed as tokens.
+    names: HashMap<usize, String>,
+    user_code: usize,   // Where the code of words not built in starts
+    input: Source,  // Input being interpreted
+    data: Vec<u8>,  // Data space, whose length is HERE
+    max_data_space: usize,
+    stack: Vec<C>,
+    loop_stack: Vec<LoopFrame<C>>,
+    return_stack: Vec<C>,
+    calls: Vec<Frame>,  // Words being executed, innermost last
+    catches: Vec<Catch>,    // CATCHes being executed, innermost last
+    step_limit: Option<usize>,
+    steps_left: usize,
+    max_call_depth: usize,
+    overflow: Overflow,
+    transactional: bool,
+    undo: Option<Undo<C>>,  // Changes made by the transactional eval running
+    output: Output,
+    trace: Vec<usize>,  // Addresses of the words being executed when an error happened
+    abort_message: Vec<u8>,     // Of the ABORT" being thrown
+
+    // Compiler state, kept between calls to `eval` so that a definition
+    // may span several of them
+    state: ParseState,
+    curr_custom_word: Option<String>,
+    body: Vec<Op<C>>,
+    control: Vec<Control>,
+    comment: bool,  // Inside a ( comment not closed by the input so far
+}
+
+// What a transactional eval changed, with the old values, so it can be
+// undone if the eval fails
+struct Undo<C> {
+    data_len: usize,    // HERE when the eval started
+    data: Vec<(usize, Vec<u8>)>,    // Old bytes below `data_len`, by address
+    words: Vec<(String, Option<Word<C>>)>,  // Names defined, with their old words
+}
+
+// Where printing words write to
+enum Output {
+    Buffer(Vec<u8>),    // Kept until `take_output`
+    Sink(Box<dyn Write>),
+}
+
+/// What arithmetic words do when a result does not fit in a cell
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Overflow {
+    Wrapping,       // Keep the low bits, as most Forth systems do
+    Checked,        // Fail with `Error::Overflow`
+    Saturating,     // Clamp to the nearest representable value
+}
+
+impl Overflow {
+    fn narrow<C: Cell>(self, r: Overflowing<C>) -> Result<C, Error> {
+        match self {
+            _ if !r.overflowed => Ok(r.wrapped),
+            Overflow::Wrapping => Ok(r.wrapped),
+            Overflow::Checked => Err(Error::Overflow),
+            Overflow::Saturating => Ok(r.saturated),
+        }
+    }
 }
 
-#[derive(Debug, PartialEq)]
+/// Errors from `eval` are `Located`, with where they happened. They compare
+/// equal to the error alone, so `Error::DivisionByZero` matches any division
+/// by zero.
+#[derive(Debug)]
 pub enum Error {
     DivisionByZero,
     StackUnderflow,
     UnknownWord,
     InvalidWord,
+    StepLimitExceeded,
+    CallDepthExceeded,
+    InvalidAddress,
+    DataSpaceOverflow,
+    Overflow,
+    ReturnStackImbalance,
+    InvalidExecutionToken,
+    Io(io::ErrorKind),  // Writing to the output sink failed
+    Throw(i32),     // THROW of a code no other variant stands for, or ABORT
+    Located { error: Box<Error>, location: Box<ErrorLocation> },
+}
+
+impl Error {
+    /// The standard THROW code for the error
+    pub fn code(&self) -> i32 {
+        match *self {
+            Error::StackUnderflow => -4,
+            Error::CallDepthExceeded => -5,
+            Error::DataSpaceOverflow => -8,
+            Error::InvalidAddress => -9,
+            Error::DivisionByZero => -10,
+            Error::Overflow => -11,
+            Error::InvalidExecutionToken => -12,
+            Error::UnknownWord => -13,
+            Error::ReturnStackImbalance => -25,
+            Error::StepLimitExceeded => -28,
+            Error::InvalidWord => -32,
+            Error::Io(_) => -37,
+            Error::Throw(code) => code,
+            Error::Located { ref error, .. } => error.code(),
+        }
+    }
+
+    /// The error without its location
+    pub fn kind(&self) -> &Error {
+        match *self {
+            Error::Located { ref error, .. } => error.kind(),
+            _ => self,
+        }
+    }
+
+    /// Where in the input to `eval` the error happened, if known
+    pub fn location(&self) -> Option<&ErrorLocation> {
+        match *self {
+            Error::Located { ref location, .. } => Some(location),
+            _ => None,
+        }
+    }
+
+    /// The error a THROW code stands for, if any variant other than `Throw`
+    /// does. Code -37 gives an `Io` error of kind `Other`.
+    pub fn from_code(code: i32) -> Option<Error> {
+        match code {
+            -4 => Some(Error::StackUnderflow),
+            -5 => Some(Error::CallDepthExceeded),
+            -8 => Some(Error::DataSpaceOverflow),
+            -9 => Some(Error::InvalidAddress),
+            -10 => Some(Error::DivisionByZero),
+            -11 => Some(Error::Overflow),
+            -12 => Some(Error::InvalidExecutionToken),
+            -13 => Some(Error::UnknownWord),
+            -25 => Some(Error::ReturnStackImbalance),
+            -28 => Some(Error::StepLimitExceeded),
+            -32 => Some(Error::InvalidWord),
+            -37 => Some(Error::Io(io::ErrorKind::Other)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::DivisionByZero => write!(f, "division by zero"),
+            Error::StackUnderflow => write!(f, "stack underflow"),
+            Error::UnknownWord => write!(f, "undefined word"),
+            Error::InvalidWord => write!(f, "invalid word"),
+            Error::StepLimitExceeded => write!(f, "step limit exceeded"),
+            Error::CallDepthExceeded => write!(f, "return stack overflow"),
+            Error::InvalidAddress => write!(f, "invalid memory address"),
+            Error::DataSpaceOverflow => write!(f, "data space overflow"),
+            Error::Overflow => write!(f, "result out of range"),
+            Error::ReturnStackImbalance => write!(f, "return stack imbalance"),
+            Error::InvalidExecutionToken => write!(f, "invalid execution token"),
+            Error::Io(kind) => write!(f, "output error: {}", kind),
+            Error::Throw(-1) | Error::Throw(-2) => write!(f, "aborted"),
+            Error::Throw(code) => write!(f, "uncaught exception {}", code),
+            Error::Located { ref error, ref location } => {
+                write!(f, "{}:{}: ", location.line, location.column)?;
+                if !location.token.is_empty() {
+                    write!(f, "{}: ", location.token)?;
+                }
+                write!(f, "{}", error)?;
+                if !location.calls.is_empty() {
+                    let mut calls = location.calls.iter().map(String::as_str).collect::<Vec<_>>();
+                    let omitted = format!("({} more)", location.calls_omitted);
+                    if location.calls_omitted > 0 {
+                        let middle = calls.len() / 2;
+                        calls.insert(middle, &omitted);
+                    }
+                    write!(f, " in {}", calls.join(" > "))?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+impl PartialEq for Error {
+    fn eq(&self, other: &Error) -> bool {
+        match (self.kind(), other.kind()) {
+            (&Error::Io(a), &Error::Io(b)) => a == b,
+            (&Error::Throw(a), &Error::Throw(b)) => a == b,
+            (a, b) => mem::discriminant(a) == mem::discriminant(b),
+        }
+    }
+}
+
+impl ::std::error::Error for Error {}
+
+/// Where in the input to `eval` an error happened
+#[derive(Debug, PartialEq, Clone)]
+pub struct ErrorLocation {
+    /// The word being interpreted, as written. Empty for errors found at
+    /// the end of the input.
+    pub token: String,
+    /// Byte range of `token` in the input
+    pub span: Range<usize>,
+    /// Line and column of the start of `token`, counting from 1, with
+    /// columns in characters
+    pub line: usize,
+    pub column: usize,
+    /// User-defined words that were executing, outermost first. Only the
+    /// outermost and innermost few are kept of a long chain.
+    pub calls: Vec<String>,
+    /// Number of words left out of the middle of `calls`
+    pub calls_omitted: usize,
 }
 
 enum ParseState {
     Normal,         // Parse into existing words
-    CustomInit,     // This item is the name of re-defined word
     Custom,         // This item is the body of re-defined word
 }
 
-impl Forth {
-    pub fn new() -> Forth {
+// Control structure awaiting resolution in the word being defined
+enum Control {
+    If(usize),      // Index of the IF branch, resolved by ELSE or THEN
+    Else(usize),    // Index of the ELSE branch, resolved by THEN
+    Do(usize, Vec<usize>),  // Index of the DO and of each LEAVE, resolved by LOOP
+    Begin(usize),   // Index of the BEGIN, target of UNTIL, AGAIN and REPEAT
+    While(usize),   // Index of the WHILE branch, resolved by REPEAT
+}
+
+impl<C: Cell> Default for Forth<C> {
+    fn default() -> Forth<C> {
+        let mut code = vec![Op::Exit, Op::EndCatch];
+        let word_map = default_word_map().into_iter().map(|(name, item)| {
+            let xt = match item {
+                Item::Code_(ref ops) => {
+                    code.extend_from_slice(ops);
+                    code.push(Op::Exit);
+                    code.len() - ops.len() - 1
+                },
+                Item::Symbol_(_) => 0,
+            };
+            (name, Word { item, xt })
+        }).collect::<HashMap<_, _>>();
+        let names = word_map.iter()
+            .filter(|&(_, word)| word.xt != 0)
+            .map(|(name, word)| (word.xt, name.clone()))
+            .collect();
+
         Forth {
-            word_map: default_word_map(),
+            word_map,
+            user_code: code.len(),
+            code,
+            latest: None,
+            stubs: HashSet::new(),
+            patches: Vec::new(),
+            names,
+            input: Source::default(),
+            data: Vec::new(),
+            max_data_space: DEFAULT_MAX_DATA_SPACE,
             stack: Vec::new(),
+            loop_stack: Vec::new(),
+            return_stack: Vec::new(),
+            calls: Vec::new(),
+            catches: Vec::new(),
+            step_limit: None,
+            steps_left: 0,
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            overflow: Overflow::Wrapping,
+            transactional: false,
+            undo: None,
+            output: Output::Buffer(Vec::new()),
+            trace: Vec::new(),
+            abort_message: Vec::new(),
+            state: ParseState::Normal,
+            curr_custom_word: None,
+            body: Vec::new(),
+            control: Vec::new(),
+            comment: false,
         }
     }
+}
+
+// Other cell types are available through `Forth::<C>::default()`
+impl Forth {
+    pub fn new() -> Forth {
+        Forth::default()
+    }
+
+    pub fn with_overflow(overflow: Overflow) -> Forth {
+        let mut f = Forth::default();
+        f.set_overflow(overflow);
+        f
+    }
+}
+
+impl<C: Cell> Forth<C> {
+    pub fn set_overflow(&mut self, overflow: Overflow) {
+        self.overflow = overflow;
+    }
 
     pub fn format_stack(&self) -> String {
         StackFormat(self).to_string()
     }
 
+    /// Contents of the return stack, bottom first. After a failed `eval` it
+    /// shows what was left behind at the point of failure.
+    pub fn return_stack(&self) -> &[C] {
+        &self.return_stack
+    }
+
+    /// Limits the number of times a single `eval` may call a word, branch
+    /// back or repeat a loop, so that runaway code fails with
+    /// `Error::StepLimitExceeded` instead of hanging.
+    pub fn set_step_limit(&mut self, limit: Option<usize>) {
+        self.step_limit = limit;
+    }
+
+    /// Limits how deeply user-defined words may call each other, so that
+    /// runaway recursion fails with `Error::CallDepthExceeded`.
+    pub fn set_max_call_depth(&mut self, depth: usize) {
+        self.max_call_depth = depth;
+    }
+
+    /// Limits the size of the data space in bytes, beyond which reserving
+    /// more of it fails with `Error::DataSpaceOverflow`.
+    pub fn set_max_data_space(&mut self, bytes: usize) {
+        self.max_data_space = bytes;
+    }
+
+    /// Sends the output of printing words such as `.` and EMIT to `sink`
+    /// instead of keeping it for `take_output`.
+    pub fn set_output<W: Write + 'static>(&mut self, sink: W) {
+        self.output = Output::Sink(Box::new(sink));
+    }
+
+    /// Output printed since the last call, unless it went to a sink set with
+    /// `set_output`. Bytes that are not valid UTF-8 are replaced.
+    pub fn take_output(&mut self) -> String {
+        match self.output {
+            Output::Buffer(ref mut buf) => String::from_utf8_lossy(&mem::take(buf)).into_owned(),
+            Output::Sink(_) => String::new(),
+        }
+    }
+
+    /// In transactional mode a failing `eval` leaves the data stack and the
+    /// dictionary exactly as they were before the call.
+    pub fn set_transactional(&mut self, transactional: bool) {
+        self.transactional = transactional;
+    }
+
+    /// Whether the input so far ended inside a `: ... ;` definition, which
+    /// the next `eval` continues.
+    pub fn is_compiling(&self) -> bool {
+        matches!(self.state, ParseState::Custom)
+    }
+
+    /// Discards the definition in progress, if any, and ends a ( comment
+    /// left open by earlier input. A failing `eval` does this as well.
+    pub fn abort_definition(&mut self) {
+        self.state = ParseState::Normal;
+        self.curr_custom_word = None;
+        self.body.clear();
+        self.control.clear();
+        self.comment = false;
+    }
+
     pub fn eval(&mut self, input: &str) -> ForthResult {
-        let v = try!(self.input_parse(input));
-        for i in v {
-            if let Item::Exec_(s) = i {
-                match s {
-                    Exec::Arith_(o) => {
-                        let (a, b) = match (self.stack.pop(), self.stack.pop()) {
-                            (Some(a), Some(b)) => (a, b),
-                            (_, _) => return Err(Error::StackUnderflow),
-                        };
-                        let v = try!(eval_oper(a, b, o));
-                        self.stack.push(v);
-                    },
-                    Exec::Stack_(c) => {
-                        try!(eval_command(&mut self.stack, c));
-                    },
-                    Exec::Value_(v) => {
-                        self.stack.push(v);
-                    },
+        if !self.transactional {
+            return self.run(input);
+        }
+
+        let stack = self.stack.clone();
+        let code = self.code.len();
+        let latest = self.latest;
+        self.undo = Some(Undo { data_len: self.data.len(), data: Vec::new(), words: Vec::new() });
+        let result = self.run(input);
+        let undo = self.undo.take().unwrap();
+        if result.is_err() {
+            self.stack = stack;
+            for (name, word) in undo.words.into_iter().rev() {
+                match word {
+                    Some(word) => self.word_map.insert(name, word),
+                    None => self.word_map.remove(&name),
+                };
+            }
+            // Stubs made before the call may have been given code by DOES>
+            for (addr, op) in self.patches.drain(..).rev() {
+                if addr < code {
+                    self.code[addr] = op;
                 }
             }
+            self.code.truncate(code);
+            self.names.retain(|&addr, _| addr < code);
+            self.stubs.retain(|&addr| addr < code);
+            self.latest = latest;
+            self.data.resize(undo.data_len, 0);
+            for (addr, bytes) in undo.data.into_iter().rev() {
+                self.data[addr..addr + bytes.len()].copy_from_slice(&bytes);
+            }
         }
-        Ok(())
+        result
     }
 
-    fn input_parse(&mut self, input: &str) -> Result<Vec<Item>, Error> {
-        let mut items = Vec::new();
-        let mut state = ParseState::Normal;
-        let mut curr_custom_word = None;
+    fn run(&mut self, input: &str) -> ForthResult {
+        self.loop_stack.clear();
+        self.return_stack.clear();
+        self.steps_left = self.step_limit.unwrap_or(usize::MAX);
+        self.trace.clear();
+        self.patches.clear();
 
-        let input_uppercased = input.to_uppercase();
-        let input_separated = to_space_separated(&input_uppercased);
-        let input_split = input_separated.split_whitespace();
+        self.input = Source::new(input);
+        if self.comment {
+            self.comment = !self.input.skip(')');
+        }
+        let mut result = self.interpret();
+        if let Err(Error::Throw(code)) = result {
+            result = self.abort(code).and(result);
+        }
+        if let Output::Sink(ref mut sink) = self.output {
+            result = result.and(sink.flush().map_err(|e| Error::Io(e.kind())));
+        }
+        let result = result.map_err(|error| Error::Located {
+            error: Box::new(error),
+            location: Box::new(self.locate_error()),
+        });
+        self.input = Source::default();
+        if result.is_err() {
+            self.abort_definition();
+        }
+        result
+    }
 
-        let first_item_is = |items: &[_], expected| {
-            match items.last() {
-                Some(&actual) => Ok(actual == expected),
-                None => Err(Error::InvalidWord),
+    // Executes or compiles each word of the input in turn, so that words
+    // run from the input can take names from the rest of it. Words are
+    // looked up regardless of case.
+    fn interpret(&mut self) -> ForthResult {
+        while let Some(item_str) = self.input.next_word().map(|w| w.to_uppercase()) {
+            // Comments are skipped whether compiling or not
+            match self.word_map.get(&item_str) {
+                Some(&Word { item: Item::Symbol_(Symbol::Paren), .. }) => {
+                    // The comment may go on in the input of the next `eval`
+                    self.comment = !self.input.skip(')');
+                    continue;
+                },
+                Some(&Word { item: Item::Symbol_(Symbol::Backslash), .. }) => {
+                    self.input.skip('\n');
+                    continue;
+                },
+                _ => {},
             }
-        };
 
-        for item_str in input_split {
-            match state {
-                ParseState::Normal => {
-                    let v = try!(self.str_to_item(item_str));
+            match self.state {
+                ParseState::Normal => self.interpret_word(&item_str)?,
+                ParseState::Custom => self.compile_word(&item_str)?,
+            }
+        }
 
-                    if try!(first_item_is(&v, Item::Symbol_(Symbol::Colon))) {
-                        state = ParseState::CustomInit;
-                    } else {
-                        items.extend(v);
-                    }
-                },
-                ParseState::CustomInit => {
-                    // Cannot re-define numbers
-                    if let Ok(v) = self.str_to_item(item_str) {
-                        let first_item = try!(v.last().clone().ok_or(Error::InvalidWord));
-
-                        if let &Item::Exec_(Exec::Value_(_)) = first_item {
-                            return Err(Error::InvalidWord);
-                        }
-                    }
+        if !self.return_stack.is_empty() {
+            return Err(Error::ReturnStackImbalance);
+        }
+        Ok(())
+    }
 
-                    let custom_word = item_str.to_owned();
-                    curr_custom_word = Some(custom_word.clone());
-                    self.word_map.insert(custom_word, Vec::new());
+    fn interpret_word(&mut self, item_str: &str) -> ForthResult {
+        if let Ok(v) = item_str.parse::<C>() {
+            self.stack.push(v);
+            return Ok(());
+        }
 
-                    state = ParseState::Custom;
-                },
-                ParseState::Custom => {
-                    let v = try!(self.str_to_item(item_str));
+        let word = self.word_map.get(item_str).cloned().ok_or(Error::UnknownWord)?;
+        match word.item {
+            Item::Symbol_(Symbol::Colon) => {
+                // The name is only added to the dictionary by `;`, so the
+                // body still sees any previous definition of it
+                self.curr_custom_word = Some(self.next_name()?);
+                self.state = ParseState::Custom;
+                Ok(())
+            },
+            Item::Symbol_(Symbol::To) => {
+                let addr = self.value_address()?;
+                let v = *self.stack.last().ok_or(Error::StackUnderflow)?;
+                self.store(addr, v)?;
+                self.stack.pop();
+                Ok(())
+            },
+            Item::Symbol_(Symbol::DotQuote) => {
+                let s = self.input.parse('"');
+                self.write(s.as_bytes())
+            },
+            Item::Symbol_(Symbol::DotParen) => {
+                let s = self.input.parse(')');
+                self.write(s.as_bytes())
+            },
+            Item::Symbol_(Symbol::SQuote) => {
+                let s = self.input.parse('"');
+                let (addr, len) = self.place_string(&s)?;
+                self.stack.extend_from_slice(&[addr, len]);
+                Ok(())
+            },
+            Item::Symbol_(Symbol::CQuote) => {
+                let s = self.input.parse('"');
+                let addr = self.place_counted_string(&s)?;
+                self.stack.push(addr);
+                Ok(())
+            },
+            // Control structures are compile-only
+            Item::Symbol_(_) => Err(Error::InvalidWord),
+            // A user-defined word is called just as it would be from another word
+            Item::Code_(code) => match code[..] {
+                [Op::Call(addr)] => self.execute(addr, true),
+                _ => self.execute(word.xt, false),
+            },
+        }
+    }
 
-                    if try!(first_item_is(&v, Item::Symbol_(Symbol::SemiColon))) {
-                        state = ParseState::Normal;
-                    } else if let Some(w) = self.word_map.get_mut(curr_custom_word.as_ref().unwrap()) {
-                        w.extend(v);
-                    }
-                },
-            }
+    fn compile_word(&mut self, item_str: &str) -> ForthResult {
+        match self.str_to_item(item_str)? {
+            Item::Symbol_(Symbol::SemiColon) => {
+                if !self.control.is_empty() {
+                    return Err(Error::InvalidWord);
+                }
+                let addr = self.code.len();
+                self.code.append(&mut self.body);
+                self.code.push(Op::Exit);
+                let name = self.curr_custom_word.take().unwrap();
+                self.define_call(name, addr);
+                self.state = ParseState::Normal;
+            },
+            // The body will be placed at the end of the code by `;`
+            Item::Symbol_(Symbol::Recurse) => self.body.push(Op::Call(self.code.len())),
+            Item::Symbol_(Symbol::To) => {
+                let addr = self.value_address()?;
+                self.body.extend_from_slice(&[Op::Lit(addr), Op::Store]);
+            },
+            Item::Symbol_(Symbol::Does) => self.body.push(Op::Does),
+            // String literals are kept in the data space
+            Item::Symbol_(Symbol::DotQuote) => {
+                let s = self.input.parse('"');
+                let (addr, len) = self.place_string(&s)?;
+                self.body.extend_from_slice(&[Op::Lit(addr), Op::Lit(len), Op::Type]);
+            },
+            Item::Symbol_(Symbol::SQuote) => {
+                let s = self.input.parse('"');
+                let (addr, len) = self.place_string(&s)?;
+                self.body.extend_from_slice(&[Op::Lit(addr), Op::Lit(len)]);
+            },
+            Item::Symbol_(Symbol::CQuote) => {
+                let s = self.input.parse('"');
+                let addr = self.place_counted_string(&s)?;
+                self.body.push(Op::Lit(addr));
+            },
+            // Prints straight away, even in a definition
+            Item::Symbol_(Symbol::DotParen) => {
+                let s = self.input.parse(')');
+                self.write(s.as_bytes())?;
+            },
+            Item::Symbol_(Symbol::BracketTick) => {
+                let xt = self.next_xt()?;
+                self.body.push(Op::Lit(C::from_usize(xt)));
+            },
+            Item::Symbol_(Symbol::AbortQuote) => {
+                let s = self.input.parse('"');
+                let (addr, len) = self.place_string(&s)?;
+                self.body.extend_from_slice(&[Op::Lit(addr), Op::Lit(len), Op::AbortQuote]);
+            },
+            Item::Symbol_(s) => compile_control(&mut self.body, &mut self.control, s)?,
+            Item::Code_(code) => self.body.extend(code),
+        }
+        Ok(())
+    }
+
+    fn str_to_item(&self, s: &str) -> Result<Item<C>, Error> {
+        match s.parse::<C>() {
+            Ok(v) => Ok(Item::Code_(vec![Op::Lit(v)])),
+            Err(_) => self.word_map.get(s).map(|word| word.item.clone()).ok_or(Error::UnknownWord),
         }
+    }
 
-        match state {
-            ParseState::Normal => Ok(items),
-            _ => Err(Error::InvalidWord),
+    // Name of a word given next in the input, as kept in the dictionary
+    fn next_word(&mut self) -> Result<String, Error> {
+        self.input.next_word().map(|w| w.to_uppercase()).ok_or(Error::InvalidWord)
+    }
+
+    // Takes the name for a new word from the input
+    fn next_name(&mut self) -> Result<String, Error> {
+        let name = self.next_word()?;
+        // Cannot re-define numbers
+        if name.parse::<C>().is_ok() {
+            return Err(Error::InvalidWord);
         }
+        Ok(name)
     }
 
-    fn str_to_item(&self, s: &str) -> Result<Vec<Item>, Error> {
-        match s.parse::<Value>() {
-            Ok(v) => Ok(vec![Item::Exec_(Exec::Value_(v))]),
-            Err(_) => self.word_map.get(&s.to_uppercase()).cloned().ok_or(Error::UnknownWord),
+    // Execution token of the word named next in the input
+    fn next_xt(&mut self) -> Result<usize, Error> {
+        let name = self.next_word()?;
+        match self.word_map.get(&name) {
+            Some(&Word { item: Item::Code_(_), xt }) => Ok(xt),
+            Some(_) => Err(Error::InvalidWord),
+            None => Err(Error::UnknownWord),
         }
     }
-}
 
-fn eval_oper(a: Value, b: Value, o: ArithWord) -> Result<Value, Error> {
-    match o {
-        ArithWord::Add => Ok(b + a),
-        ArithWord::Sub => Ok(b - a),
-        ArithWord::Mul => Ok(b * a),
-        ArithWord::Div => {
-            match a {
-                0 => Err(Error::DivisionByZero),
-                a => Ok(b / a),
-            }
-        },
+    // Address of the word made by VALUE named next in the input, which is
+    // the only kind of word compiled to a fetch from a fixed address
+    fn value_address(&mut self) -> Result<C, Error> {
+        let name = self.next_word()?;
+        match self.word_map.get(&name) {
+            Some(Word { item: Item::Code_(code), .. }) => match code[..] {
+                [Op::Lit(addr), Op::Fetch] => Ok(addr),
+                _ => Err(Error::InvalidWord),
+            },
+            Some(_) => Err(Error::InvalidWord),
+            None => Err(Error::UnknownWord),
+        }
+    }
+
+    // Copies `s` to HERE and returns its address and length
+    fn place_string(&mut self, s: &str) -> Result<(C, C), Error> {
+        let addr = self.reserve(s.len())?;
+        self.data[addr..].copy_from_slice(s.as_bytes());
+        Ok((C::from_usize(addr), C::from_usize(s.len())))
+    }
+
+    // Copies `s` to HERE preceded by its length in a byte, which limits it
+    // to 255 bytes
+    fn place_counted_string(&mut self, s: &str) -> Result<C, Error> {
+        if s.len() > u8::MAX as usize {
+            return Err(Error::InvalidWord);
+        }
+        let addr = self.reserve(s.len() + 1)?;
+        self.data[addr] = s.len() as u8;
+        self.data[addr + 1..].copy_from_slice(s.as_bytes());
+        Ok(C::from_usize(addr))
+    }
+
+    // Adds a word whose code is compiled in place wherever it is used
+    fn define(&mut self, name: String, code: Vec<Op<C>>) {
+        let xt = self.code.len();
+        self.code.extend_from_slice(&code);
+        self.code.push(Op::Exit);
+        self.names.insert(xt, name.clone());
+        self.insert_word(name, Word { item: Item::Code_(code), xt });
+        // DOES> only changes a word if CREATE made the latest one
+        self.latest = None;
+    }
+
+    // Does what the standard asks of an uncaught ABORT or ABORT"
+    fn abort(&mut self, code: i32) -> ForthResult {
+        let message = mem::take(&mut self.abort_message);
+        match code {
+            -1 => self.stack.clear(),
+            -2 => {
+                self.stack.clear();
+                self.write(&message)?;
+            },
+            _ => {},
+        }
+        Ok(())
+    }
+
+    // The last word read from the input, along with the words executing
+    // when the error happened, if it happened in one of them
+    fn locate_error(&self) -> ErrorLocation {
+        let (line, column) = self.input.word_position();
+        let calls = self.trace.iter()
+            .filter(|&&addr| addr >= self.user_code)
+            .collect::<Vec<_>>();
+        let calls_omitted = calls.len().saturating_sub(2 * ERROR_CALLS_KEPT);
+        let (outer, inner) = if calls_omitted > 0 {
+            (&calls[..ERROR_CALLS_KEPT], &calls[calls.len() - ERROR_CALLS_KEPT..])
+        } else {
+            (&calls[..], &[][..])
+        };
+        ErrorLocation {
+            token: self.input.word().to_owned(),
+            span: self.input.word_span(),
+            line,
+            column,
+            calls: outer.iter().chain(inner)
+                .filter_map(|addr| self.names.get(addr).cloned())
+                .collect(),
+            calls_omitted,
+        }
+    }
+
+    // Adds a word that calls the code at `addr`. CREATE sets `latest` after
+    // this.
+    fn define_call(&mut self, name: String, addr: usize) {
+        self.names.insert(addr, name.clone());
+        self.insert_word(name, Word { item: Item::Code_(vec![Op::Call(addr)]), xt: addr });
+        self.latest = None;
+    }
+
+    fn insert_word(&mut self, name: String, word: Word<C>) {
+        match self.undo {
+            Some(ref mut undo) => {
+                let old = self.word_map.insert(name.clone(), word);
+                undo.words.push((name, old));
+            },
+            None => {
+                self.word_map.insert(name, word);
+            },
+        }
     }
 }
 
-fn eval_command(stack: &mut Vec<Value>, c: StackWord) -> ForthResult {
-    match c {
-        StackWord::Dup => {
-            let a = try!(stack.last().cloned().ok_or(Error::StackUnderflow));
-            stack.push(a);
+// Compiles a control-flow word into the body of the word being defined
+fn compile_control<C: Cell>(body: &mut Vec<Op<C>>, control: &mut Vec<Control>, s: Symbol) -> ForthResult {
+    match s {
+        Symbol::If => {
+            control.push(Control::If(body.len()));
+            body.push(Op::BranchIfZero(0));
+        },
+        Symbol::Else => {
+            let orig = match control.pop() {
+                Some(Control::If(orig)) => orig,
+                _ => return Err(Error::InvalidWord),
+            };
+            control.push(Control::Else(body.len()));
+            body.push(Op::Branch(0));
+            resolve_branch(body, orig);
         },
-        StackWord::Drop => {
-            if stack.pop().is_none() {
-                return Err(Error::StackUnderflow);
+        Symbol::Then => {
+            match control.pop() {
+                Some(Control::If(orig)) | Some(Control::Else(orig)) |
+                Some(Control::While(orig)) => resolve_branch(body, orig),
+                _ => return Err(Error::InvalidWord),
             }
         },
-        StackWord::Swap => {
-            match (stack.pop(), stack.pop()) {
-                (Some(a), Some(b)) => {
-                    stack.push(a);
-                    stack.push(b);
-                },
-                (_, _) => return Err(Error::StackUnderflow),
+        Symbol::Do => {
+            control.push(Control::Do(body.len(), Vec::new()));
+            body.push(Op::Do);
+        },
+        Symbol::QDo => {
+            control.push(Control::Do(body.len(), Vec::new()));
+            body.push(Op::QDo(0));
+        },
+        Symbol::Loop | Symbol::PlusLoop => {
+            let (dest, leaves) = match control.pop() {
+                Some(Control::Do(dest, leaves)) => (dest, leaves),
+                _ => return Err(Error::InvalidWord),
+            };
+            // Back to the first instruction after the DO
+            let offset = dest as isize - body.len() as isize;
+            body.push(if s == Symbol::Loop { Op::Loop(offset) } else { Op::PlusLoop(offset) });
+
+            if let Op::QDo(_) = body[dest] {
+                resolve_branch(body, dest);
+            }
+            for orig in leaves {
+                resolve_branch(body, orig);
             }
         },
-        StackWord::Over => {
-            let len = stack.len();
-            if len < 2 { return Err(Error::StackUnderflow) };
-            let a = try!(stack.get(len - 2).cloned().ok_or(Error::StackUnderflow));
-            stack.push(a);
+        Symbol::Leave => {
+            let leaves = control.iter_mut().rev().filter_map(|c| match *c {
+                Control::Do(_, ref mut leaves) => Some(leaves),
+                _ => None,
+            }).next();
+            match leaves {
+                Some(leaves) => leaves.push(body.len()),
+                None => return Err(Error::InvalidWord),
+            }
+            body.push(Op::Leave(0));
+        },
+        Symbol::Begin => control.push(Control::Begin(body.len())),
+        Symbol::Until | Symbol::Again => {
+            let dest = match control.pop() {
+                Some(Control::Begin(dest)) => dest,
+                _ => return Err(Error::InvalidWord),
+            };
+            let offset = dest as isize - body.len() as isize - 1;
+            body.push(if s == Symbol::Until { Op::BranchIfZero(offset) } else { Op::Branch(offset) });
+        },
+        Symbol::While => {
+            let dest = match control.pop() {
+                Some(Control::Begin(dest)) => dest,
+                _ => return Err(Error::InvalidWord),
+            };
+            control.push(Control::While(body.len()));
+            control.push(Control::Begin(dest));
+            body.push(Op::BranchIfZero(0));
+        },
+        Symbol::Repeat => {
+            let dest = match control.pop() {
+                Some(Control::Begin(dest)) => dest,
+                _ => return Err(Error::InvalidWord),
+            };
+            let orig = match control.pop() {
+                Some(Control::While(orig)) => orig,
+                _ => return Err(Error::InvalidWord),
+            };
+            let offset = dest as isize - body.len() as isize - 1;
+            body.push(Op::Branch(offset));
+            resolve_branch(body, orig);
         },
+        Symbol::Colon | Symbol::SemiColon | Symbol::Recurse | Symbol::To |
+        Symbol::Does | Symbol::DotQuote | Symbol::SQuote | Symbol::CQuote |
+        Symbol::DotParen | Symbol::Paren | Symbol::Backslash |
+        Symbol::AbortQuote | Symbol::BracketTick => return Err(Error::InvalidWord),
     }
     Ok(())
 }
 
-fn to_space_separated(s: &str) -> String {
-    s.chars().map(|c| if c.is_control() { ' ' } else { c }).collect()
+// Points the forward branch at `orig` to the end of `body`
+fn resolve_branch<C: Cell>(body: &mut [Op<C>], orig: usize) {
+    let offset = (body.len() - orig - 1) as isize;
+    body[orig] = match body[orig] {
+        Op::Branch(_) => Op::Branch(offset),
+        Op::BranchIfZero(_) => Op::BranchIfZero(offset),
+        Op::QDo(_) => Op::QDo(offset),
+        Op::Leave(_) => Op::Leave(offset),
+        op => op,
+    };
 }
 
-struct StackFormat<'a>(&'a Forth);
+struct StackFormat<'a, C: Cell + 'a>(&'a Forth<C>);
 
-impl<'a> fmt::Display for StackFormat<'a> {
+impl<'a, C: Cell> fmt::Display for StackFormat<'a, C> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         if let Some((head, tail)) = self.0.stack.split_first() {
-            try!(write!(f, "{}", head));
+            write!(f, "{}", head)?;
 
             for v in tail {
-                try!(write!(f, " {}", v));
+                write!(f, " {}", v)?;
             }
         }
         Ok(())
@@ -0,0 +1,86 @@
+use std::ops::Range;
+
+// Text passed to `eval`, read one word or delimited string at a time
+#[derive(Default)]
+pub struct Source {
+    text: String,
+    pos: usize,     // Byte offset of the part not read yet
+    word: Range<usize>,     // Last word read, or the empty end of the text
+}
+
+impl Source {
+    pub fn new(text: &str) -> Source {
+        Source { text: text.to_owned(), pos: 0, word: 0..0 }
+    }
+
+    // Next word as written, or None at the end of the text
+    pub fn next_word(&mut self) -> Option<String> {
+        let rest = &self.text[self.pos..];
+        let start = match rest.find(|c| !is_space(c)) {
+            Some(start) => self.pos + start,
+            None => {
+                self.pos = self.text.len();
+                self.word = self.pos..self.pos;
+                return None;
+            },
+        };
+        let rest = &self.text[start..];
+        let len = rest.find(is_space).unwrap_or(rest.len());
+        self.pos = start + len;
+        self.word = start..self.pos;
+        Some(rest[..len].to_owned())
+    }
+
+    pub fn word(&self) -> &str {
+        &self.text[self.word.clone()]
+    }
+
+    pub fn word_span(&self) -> Range<usize> {
+        self.word.clone()
+    }
+
+    // Line and column of the start of the last word, counting from 1
+    pub fn word_position(&self) -> (usize, usize) {
+        let before = &self.text[..self.word.start];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        let line = before.matches('\n').count() + 1;
+        let column = before[line_start..].chars().count() + 1;
+        (line, column)
+    }
+
+    // Text up to the next `delim`, after the one space that ends the word
+    // parsing it. Takes the rest of the text if there is no `delim`.
+    pub fn parse(&mut self, delim: char) -> String {
+        if let Some(c) = self.text[self.pos..].chars().next().filter(|&c| is_space(c)) {
+            self.pos += c.len_utf8();
+        }
+        let rest = &self.text[self.pos..];
+        let (s, len) = match rest.find(delim) {
+            Some(end) => (&rest[..end], end + delim.len_utf8()),
+            None => (rest, rest.len()),
+        };
+        let s = s.to_owned();
+        self.pos += len;
+        s
+    }
+
+    // Skips past the next `delim` and returns true, or to the end of the
+    // text and returns false if there is none
+    pub fn skip(&mut self, delim: char) -> bool {
+        match self.text[self.pos..].find(delim) {
+            Some(end) => {
+                self.pos += end + delim.len_utf8();
+                true
+            },
+            None => {
+                self.pos = self.text.len();
+                false
+            },
+        }
+    }
+}
+
+// Control characters separate words just like whitespace
+fn is_space(c: char) -> bool {
+    c.is_whitespace() || c.is_control()
+}
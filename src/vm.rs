@@ -0,0 +1,790 @@
+use std::ops::Range;
+
+use std::io::Write;
+use std::mem;
+
+use super::{Cell, Error, Forth, ForthResult, Output, Overflow};
+
+/// A single instruction of the threaded code that `Forth` compiles words to.
+///
+/// Branch offsets are relative to the instruction following the branch, so
+/// a compiled body stays valid wherever it is placed in the code arena.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Op<C> {
+    Lit(C),
+
+    Add, Sub, Mul, Div, Mod, Min, Max,
+    Eq, Ne, Lt, Gt, ULt,
+    And, Or, Xor, LShift, RShift,
+    Negate, Abs, TwoDiv,
+    // Words that would otherwise be a literal and one of the ops above
+    OnePlus, OneMinus, TwoMul, ZeroEq, ZeroLt, ZeroGt, Invert,
+    DivMod, StarSlash, StarSlashMod, FmMod, SmRem,
+
+    Dup, Drop, Swap, Over,
+    Rot, MinusRot, Nip, Tuck, Pick, Roll, QDup, Depth,
+    TwoDup, TwoDrop, TwoSwap, TwoOver, TwoRot,
+
+    Branch(isize),
+    BranchIfZero(isize),
+    Do,
+    QDo(isize),         // Skips the loop when limit and index are equal
+    Loop(isize),
+    PlusLoop(isize),
+    Leave(isize),
+    I, J, Unloop,
+
+    ToR, FromR, RFetch, TwoToR, TwoFromR,
+
+    Fetch, Store, PlusStore, CFetch, CStore,
+    Here, Allot, Comma, CComma, Align, Aligned,
+    Cells, CellPlus, Fill, Erase, Move,
+
+    // Words that take the name of a new word from the input
+    Variable, Constant, Value, Create,
+    Does,               // Ends the word, giving its remaining code to the last CREATE
+    ToBody,
+    Tick,
+    Execute,
+
+    Emit, Cr, Space, Dot, UDot, DotR, Spaces, Type,
+
+    Catch,
+    EndCatch,           // Where words run by CATCH return to
+    Throw,
+    Abort,
+    AbortQuote,         // Throws -2 with the message given by address and length
+
+    Call(usize),        // Address of a user-defined word in the code arena
+    Exit,
+}
+
+// Address of the `EndCatch` in the code
+pub const END_CATCH: usize = 1;
+
+// Where to resume once a user-defined word returns
+pub struct Frame {
+    addr: usize,    // Start of the called word
+    ret: usize,
+    return_depth: usize,    // Return stack depth on entry to the called word
+}
+
+// Exception frame of a CATCH, holding what to restore if the word it runs
+// throws
+pub struct Catch {
+    calls: usize,
+    depth: usize,
+    return_depth: usize,
+    loop_depth: usize,
+    ret: usize,     // Instruction after the CATCH
+}
+
+// Loop-control parameters of an active DO loop
+#[derive(Debug, Copy, Clone)]
+pub struct LoopFrame<C> {
+    index: C,
+    limit: C,
+}
+
+impl<C: Cell> Forth<C> {
+    // Reserves `n` zeroed bytes at HERE and returns their address. HERE
+    // must stay small enough to fit in a cell.
+    pub(super) fn reserve(&mut self, n: usize) -> Result<usize, Error> {
+        let here = self.data.len();
+        let max = C::MAX.to_usize().map_or(self.max_data_space, |max| max.min(self.max_data_space));
+        if n > max.saturating_sub(here) {
+            return Err(Error::DataSpaceOverflow);
+        }
+        self.data.resize(here + n, 0);
+        Ok(here)
+    }
+
+    pub(super) fn align(&mut self) -> ForthResult {
+        let padding = (C::BYTES - self.data.len() % C::BYTES) % C::BYTES;
+        self.reserve(padding).map(|_| ())
+    }
+
+    // Moves HERE by `n` bytes, which may be negative to release space again
+    fn allot(&mut self, n: C) -> ForthResult {
+        if n >= C::from(0) {
+            let n = n.to_usize().ok_or(Error::DataSpaceOverflow)?;
+            return self.reserve(n).map(|_| ());
+        }
+        let n = n.neg().wrapped.to_usize().ok_or(Error::InvalidAddress)?;
+        let here = self.data.len().checked_sub(n).ok_or(Error::InvalidAddress)?;
+        self.save_data(here..self.data.len());
+        self.data.truncate(here);
+        Ok(())
+    }
+
+    // Runs the code starting at `ip`, as a call of its own if `called`, until
+    // it exits from the outermost level
+    pub(super) fn execute(&mut self, mut ip: usize, called: bool) -> ForthResult {
+        // Both are kept between calls so that they need not grow again
+        self.calls.clear();
+        self.catches.clear();
+        if called {
+            // The code at address 0 exits
+            self.calls.push(Frame { addr: ip, ret: 0, return_depth: self.return_stack.len() });
+        }
+
+        loop {
+            let e = match self.run_code(ip) {
+                Ok(()) => return Ok(()),
+                Err(e) => e,
+            };
+            // The step limit is for the embedder to stop runaway code, so
+            // it cannot be caught
+            let catch = match self.catches.pop() {
+                Some(catch) if e != Error::StepLimitExceeded => catch,
+                _ => {
+                    self.trace = self.calls.iter().map(|frame| frame.addr).collect();
+                    return Err(e);
+                },
+            };
+
+            // Only the depth of the data stack is restored; cells that
+            // were dropped since the CATCH come back as zero
+            self.calls.truncate(catch.calls);
+            self.stack.resize(catch.depth, C::from(0));
+            self.return_stack.truncate(catch.return_depth);
+            self.loop_stack.truncate(catch.loop_depth);
+            self.abort_message.clear();
+            self.stack.push(C::from_i32(e.code()));
+            ip = catch.ret;
+        }
+    }
+
+    fn run_code(&mut self, mut ip: usize) -> ForthResult {
+        let overflow = self.overflow;
+        loop {
+            // Every word ends in an exit and every branch, call and return
+            // address is one the compiler made, so `ip` stays in the code.
+            // Rolling back a failed transactional eval removes only code
+            // that nothing older refers to.
+            debug_assert!(ip < self.code.len());
+            let op = unsafe { *self.code.get_unchecked(ip) };
+            ip += 1;
+            let stack = &mut self.stack;
+            match op {
+                Op::Lit(v) => stack.push(v),
+
+                Op::Add => binary(stack, |b, a| overflow.narrow(b.add(a)))?,
+                Op::Sub => binary(stack, |b, a| overflow.narrow(b.sub(a)))?,
+                Op::Mul => binary(stack, |b, a| overflow.narrow(b.mul(a)))?,
+                Op::Div => binary(stack, |b, a| match a {
+                    a if a == C::from(0) => Err(Error::DivisionByZero),
+                    a => overflow.narrow(b.div(a)),
+                })?,
+                // The remainder always fits, even for MIN / -1
+                Op::Mod => binary(stack, |b, a| match a {
+                    a if a == C::from(0) => Err(Error::DivisionByZero),
+                    a => Ok(b.wrapping_rem(a)),
+                })?,
+                Op::Min => binary(stack, |b, a| Ok(b.min(a)))?,
+                Op::Max => binary(stack, |b, a| Ok(b.max(a)))?,
+                Op::Eq => binary(stack, |b, a| Ok(flag(b == a)))?,
+                Op::Ne => binary(stack, |b, a| Ok(flag(b != a)))?,
+                Op::Lt => binary(stack, |b, a| Ok(flag(b < a)))?,
+                Op::Gt => binary(stack, |b, a| Ok(flag(b > a)))?,
+                Op::ULt => binary(stack, |b, a| Ok(flag(b.unsigned_lt(a))))?,
+                Op::And => binary(stack, |b, a| Ok(b & a))?,
+                Op::Or => binary(stack, |b, a| Ok(b | a))?,
+                Op::Xor => binary(stack, |b, a| Ok(b ^ a))?,
+                Op::LShift => binary(stack, |b, a| Ok(b.lshift(a)))?,
+                Op::RShift => binary(stack, |b, a| Ok(b.rshift(a)))?,
+                Op::Negate => unary(stack, |a| overflow.narrow(a.neg()))?,
+                Op::Abs => unary(stack, |a| overflow.narrow(a.abs()))?,
+                Op::TwoDiv => unary(stack, |a| Ok(a >> 1))?,
+                Op::OnePlus => unary(stack, |a| overflow.narrow(a.add(C::from(1))))?,
+                Op::OneMinus => unary(stack, |a| overflow.narrow(a.sub(C::from(1))))?,
+                Op::TwoMul => unary(stack, |a| overflow.narrow(a.mul(C::from(2))))?,
+                Op::ZeroEq => unary(stack, |a| Ok(flag(a == C::from(0))))?,
+                Op::ZeroLt => unary(stack, |a| Ok(flag(a < C::from(0))))?,
+                Op::ZeroGt => unary(stack, |a| Ok(flag(a > C::from(0))))?,
+                Op::Invert => unary(stack, |a| Ok(a ^ C::from(-1)))?,
+                Op::DivMod | Op::StarSlash | Op::StarSlashMod | Op::FmMod | Op::SmRem => {
+                    eval_div(stack, op, overflow)?;
+                },
+
+                Op::Dup => {
+                    let a = *stack.last().ok_or(Error::StackUnderflow)?;
+                    stack.push(a);
+                },
+                Op::Drop => {
+                    stack.pop().ok_or(Error::StackUnderflow)?;
+                },
+                Op::Swap => top_mut(stack, 2)?.swap(0, 1),
+                Op::Over => {
+                    let a = top_mut(stack, 2)?[0];
+                    stack.push(a);
+                },
+                Op::Rot => top_mut(stack, 3)?.rotate_left(1),
+                Op::MinusRot => top_mut(stack, 3)?.rotate_right(1),
+                Op::Nip => {
+                    let a = top_mut(stack, 2)?[1];
+                    stack.pop();
+                    *stack.last_mut().unwrap() = a;
+                },
+                Op::Tuck => {
+                    let a = top_mut(stack, 2)?[1];
+                    let len = stack.len();
+                    stack.insert(len - 2, a);
+                },
+                Op::Pick => {
+                    let u = pick_index(stack)?;
+                    let len = stack.len();
+                    stack[len - 1] = stack[len - 2 - u];
+                },
+                Op::Roll => {
+                    let u = pick_index(stack)?;
+                    stack.pop();
+                    top_mut(stack, u + 1)?.rotate_left(1);
+                },
+                Op::QDup => {
+                    let a = top_mut(stack, 1)?[0];
+                    if a != C::from(0) {
+                        stack.push(a);
+                    }
+                },
+                Op::Depth => {
+                    let len = stack.len();
+                    stack.push(C::from_usize(len));
+                },
+                Op::TwoDup => {
+                    top_mut(stack, 2)?;
+                    let len = stack.len();
+                    stack.extend_from_within(len - 2..);
+                },
+                Op::TwoDrop => {
+                    top_mut(stack, 2)?;
+                    let len = stack.len();
+                    stack.truncate(len - 2);
+                },
+                Op::TwoSwap => top_mut(stack, 4)?.rotate_left(2),
+                Op::TwoOver => {
+                    top_mut(stack, 4)?;
+                    let len = stack.len();
+                    stack.extend_from_within(len - 4..len - 2);
+                },
+                Op::TwoRot => top_mut(stack, 6)?.rotate_left(2),
+
+                Op::Branch(offset) => {
+                    if offset < 0 {
+                        count_step(&mut self.steps_left)?;
+                    }
+                    ip = jump(ip, offset);
+                },
+                Op::BranchIfZero(offset) => {
+                    if stack.pop().ok_or(Error::StackUnderflow)? == C::from(0) {
+                        if offset < 0 {
+                            count_step(&mut self.steps_left)?;
+                        }
+                        ip = jump(ip, offset);
+                    }
+                },
+                Op::Do => {
+                    let (index, limit) = pop_pair(stack)?;
+                    self.loop_stack.push(LoopFrame { index, limit });
+                },
+                Op::QDo(offset) => {
+                    let (index, limit) = pop_pair(stack)?;
+                    if index == limit {
+                        ip = jump(ip, offset);
+                    } else {
+                        self.loop_stack.push(LoopFrame { index, limit });
+                    }
+                },
+                Op::Loop(offset) => {
+                    if loop_step(&mut self.loop_stack, C::from(1))? {
+                        count_step(&mut self.steps_left)?;
+                        ip = jump(ip, offset);
+                    }
+                },
+                Op::PlusLoop(offset) => {
+                    let step = *stack.last().ok_or(Error::StackUnderflow)?;
+                    let repeat = loop_step(&mut self.loop_stack, step)?;
+                    stack.pop();
+                    if repeat {
+                        count_step(&mut self.steps_left)?;
+                        ip = jump(ip, offset);
+                    }
+                },
+                Op::Leave(offset) => {
+                    self.loop_stack.pop().ok_or(Error::StackUnderflow)?;
+                    ip = jump(ip, offset);
+                },
+                Op::I => {
+                    let frame = self.loop_stack.last().ok_or(Error::StackUnderflow)?;
+                    stack.push(frame.index);
+                },
+                Op::J => {
+                    let len = self.loop_stack.len();
+                    if len < 2 { return Err(Error::StackUnderflow) };
+                    stack.push(self.loop_stack[len - 2].index);
+                },
+                Op::Unloop => {
+                    self.loop_stack.pop().ok_or(Error::StackUnderflow)?;
+                },
+
+                Op::ToR => to_return_stack(stack, &mut self.return_stack, 1)?,
+                Op::TwoToR => to_return_stack(stack, &mut self.return_stack, 2)?,
+                Op::FromR | Op::TwoFromR | Op::RFetch => {
+                    // Words may only take from the return stack what they
+                    // put there themselves
+                    let base = self.calls.last().map_or(0, |frame| frame.return_depth);
+                    let available = self.return_stack.len() - base;
+                    let n = if op == Op::TwoFromR { 2 } else { 1 };
+                    if available < n { return Err(Error::ReturnStackImbalance) };
+
+                    let len = self.return_stack.len();
+                    if op == Op::RFetch {
+                        stack.push(self.return_stack[len - 1]);
+                    } else {
+                        stack.extend(self.return_stack.drain(len - n..));
+                    }
+                },
+
+                Op::Fetch | Op::CFetch => {
+                    let addr = *stack.last().ok_or(Error::StackUnderflow)?;
+                    let v = if op == Op::Fetch {
+                        C::load(&self.data[data_range(&self.data, addr, C::BYTES)?])
+                    } else {
+                        C::from_usize(self.data[data_range(&self.data, addr, 1)?.start] as usize)
+                    };
+                    *stack.last_mut().unwrap() = v;
+                },
+                Op::Store | Op::PlusStore | Op::CStore => {
+                    let (v, addr) = {
+                        let top = top_mut(stack, 2)?;
+                        (top[0], top[1])
+                    };
+                    if op == Op::CStore {
+                        let range = data_range(&self.data, addr, 1)?;
+                        self.data_mut(range)[0] = v.low_byte();
+                    } else if op == Op::Store {
+                        self.store(addr, v)?;
+                    } else {
+                        let range = data_range(&self.data, addr, C::BYTES)?;
+                        let bytes = self.data_mut(range);
+                        overflow.narrow(C::load(bytes).add(v))?.store(bytes);
+                    }
+                    let len = self.stack.len();
+                    self.stack.truncate(len - 2);
+                },
+                Op::Here => stack.push(C::from_usize(self.data.len())),
+                Op::Allot => {
+                    let n = *stack.last().ok_or(Error::StackUnderflow)?;
+                    self.allot(n)?;
+                    self.stack.pop();
+                },
+                Op::Comma | Op::CComma => {
+                    let v = *stack.last().ok_or(Error::StackUnderflow)?;
+                    if op == Op::Comma {
+                        let addr = self.reserve(C::BYTES)?;
+                        v.store(&mut self.data[addr..]);
+                    } else {
+                        let addr = self.reserve(1)?;
+                        self.data[addr] = v.low_byte();
+                    }
+                    self.stack.pop();
+                },
+                Op::Align => self.align()?,
+                Op::Aligned => unary(stack, |a| {
+                    // Cell sizes are powers of two, so the negated size is a mask
+                    let bytes = C::from_usize(C::BYTES);
+                    Ok(a.wrapping_add(bytes).wrapping_sub(C::from(1)) & bytes.neg().wrapped)
+                })?,
+                Op::Cells => unary(stack, |a| overflow.narrow(a.mul(C::from_usize(C::BYTES))))?,
+                Op::CellPlus => unary(stack, |a| overflow.narrow(a.add(C::from_usize(C::BYTES))))?,
+                Op::Fill | Op::Erase => {
+                    let n = if op == Op::Fill { 3 } else { 2 };
+                    let (addr, u, c) = {
+                        let top = top_mut(stack, n)?;
+                        (top[0], top[1], if op == Op::Fill { top[2] } else { C::from(0) })
+                    };
+                    // A count of zero does nothing, whatever the address
+                    let u = u.to_usize().ok_or(Error::InvalidAddress)?;
+                    if u != 0 {
+                        let range = data_range(&self.data, addr, u)?;
+                        for b in self.data_mut(range) {
+                            *b = c.low_byte();
+                        }
+                    }
+                    let len = self.stack.len();
+                    self.stack.truncate(len - n);
+                },
+                Op::Move => {
+                    let (from, to, u) = {
+                        let top = top_mut(stack, 3)?;
+                        (top[0], top[1], top[2])
+                    };
+                    let u = u.to_usize().ok_or(Error::InvalidAddress)?;
+                    if u != 0 {
+                        let from = data_range(&self.data, from, u)?;
+                        let to = data_range(&self.data, to, u)?;
+                        self.save_data(to.clone());
+                        self.data.copy_within(from, to.start);
+                    }
+                    let len = self.stack.len();
+                    self.stack.truncate(len - 3);
+                },
+
+                Op::Variable | Op::Create => {
+                    let name = self.next_name()?;
+                    self.align()?;
+                    let addr = C::from_usize(self.data.len());
+                    if op == Op::Variable {
+                        self.reserve(C::BYTES)?;
+                        self.define(name, vec![Op::Lit(addr)]);
+                    } else {
+                        let stub = self.code.len();
+                        self.code.extend_from_slice(&[Op::Lit(addr), Op::Exit]);
+                        self.define_call(name, stub);
+                        self.stubs.insert(stub);
+                        self.latest = Some(stub);
+                    }
+                },
+                Op::Constant | Op::Value => {
+                    let v = *stack.last().ok_or(Error::StackUnderflow)?;
+                    let name = self.next_name()?;
+                    if op == Op::Constant {
+                        self.define(name, vec![Op::Lit(v)]);
+                    } else {
+                        self.align()?;
+                        let addr = C::from_usize(self.reserve(C::BYTES)?);
+                        self.store(addr, v)?;
+                        self.define(name, vec![Op::Lit(addr), Op::Fetch]);
+                    }
+                    self.stack.pop();
+                },
+                Op::ToBody => {
+                    let xt = *stack.last().ok_or(Error::StackUnderflow)?;
+                    // Only words made by CREATE have a data field
+                    let stubs = &self.stubs;
+                    let addr = match xt.to_usize().filter(|xt| stubs.contains(xt)) {
+                        Some(stub) => match self.code[stub] {
+                            Op::Lit(addr) => addr,
+                            _ => unreachable!(),
+                        },
+                        None => return Err(Error::InvalidWord),
+                    };
+                    *stack.last_mut().unwrap() = addr;
+                },
+                Op::Tick => {
+                    let xt = self.next_xt()?;
+                    self.stack.push(C::from_usize(xt));
+                },
+
+                Op::Emit => {
+                    let c = *stack.last().ok_or(Error::StackUnderflow)?;
+                    self.write(&[c.low_byte()])?;
+                    self.stack.pop();
+                },
+                Op::Cr => self.write(b"\n")?,
+                Op::Space => self.write(b" ")?,
+                Op::Dot | Op::UDot => {
+                    let n = *stack.last().ok_or(Error::StackUnderflow)?;
+                    let s = if op == Op::Dot { format!("{} ", n) } else { format!("{} ", n.unsigned()) };
+                    self.write(s.as_bytes())?;
+                    self.stack.pop();
+                },
+                Op::DotR => {
+                    let (n, width) = {
+                        let top = top_mut(stack, 2)?;
+                        (top[0], top[1])
+                    };
+                    let s = n.to_string();
+                    let width = width.to_usize().unwrap_or(0);
+                    self.write_spaces(width.saturating_sub(s.len()))?;
+                    self.write(s.as_bytes())?;
+                    let len = self.stack.len();
+                    self.stack.truncate(len - 2);
+                },
+                Op::Spaces => {
+                    let n = *stack.last().ok_or(Error::StackUnderflow)?;
+                    self.write_spaces(n.to_usize().unwrap_or(0))?;
+                    self.stack.pop();
+                },
+                Op::Type => {
+                    let (addr, u) = {
+                        let top = top_mut(stack, 2)?;
+                        (top[0], top[1])
+                    };
+                    let u = u.to_usize().ok_or(Error::InvalidAddress)?;
+                    let range = data_range(&self.data, addr, u)?;
+                    let text = self.data[range].to_vec();
+                    self.write(&text)?;
+                    let len = self.stack.len();
+                    self.stack.truncate(len - 2);
+                },
+
+                Op::Execute => {
+                    let xt = self.check_xt()?;
+                    count_step(&mut self.steps_left)?;
+                    if self.calls.len() >= self.max_call_depth {
+                        return Err(Error::CallDepthExceeded);
+                    }
+                    self.stack.pop();
+                    self.calls.push(Frame { addr: xt, ret: ip, return_depth: self.return_stack.len() });
+                    ip = xt;
+                },
+
+                Op::Catch => {
+                    let xt = self.check_xt()?;
+                    count_step(&mut self.steps_left)?;
+                    if self.calls.len() >= self.max_call_depth {
+                        return Err(Error::CallDepthExceeded);
+                    }
+                    self.stack.pop();
+                    self.catches.push(Catch {
+                        calls: self.calls.len(),
+                        depth: self.stack.len(),
+                        return_depth: self.return_stack.len(),
+                        loop_depth: self.loop_stack.len(),
+                        ret: ip,
+                    });
+                    self.calls.push(Frame { addr: xt, ret: END_CATCH, return_depth: self.return_stack.len() });
+                    ip = xt;
+                },
+                Op::EndCatch => {
+                    // Only reached by returning from the word run by the
+                    // innermost CATCH
+                    let catch = self.catches.pop().unwrap();
+                    self.stack.push(C::from(0));
+                    ip = catch.ret;
+                },
+                Op::Throw => {
+                    let n = *stack.last().ok_or(Error::StackUnderflow)?;
+                    if n != C::from(0) {
+                        // Codes that do not fit the error are out of range
+                        let n = n.to_i32().ok_or(Error::Overflow)?;
+                        self.stack.pop();
+                        return Err(Error::from_code(n).unwrap_or(Error::Throw(n)));
+                    }
+                    stack.pop();
+                },
+                Op::Abort => return Err(Error::Throw(-1)),
+                Op::AbortQuote => {
+                    let (flag, addr, u) = {
+                        let top = top_mut(stack, 3)?;
+                        (top[0], top[1], top[2])
+                    };
+                    if flag != C::from(0) {
+                        let u = u.to_usize().ok_or(Error::InvalidAddress)?;
+                        let range = data_range(&self.data, addr, u)?;
+                        self.abort_message = self.data[range].to_vec();
+                        return Err(Error::Throw(-2));
+                    }
+                    let len = self.stack.len();
+                    self.stack.truncate(len - 3);
+                },
+
+                Op::Call(addr) => {
+                    count_step(&mut self.steps_left)?;
+                    if self.calls.len() >= self.max_call_depth {
+                        return Err(Error::CallDepthExceeded);
+                    }
+                    self.calls.push(Frame { addr, ret: ip, return_depth: self.return_stack.len() });
+                    ip = addr;
+                },
+                Op::Exit | Op::Does => {
+                    if op == Op::Does {
+                        let stub = self.latest.ok_or(Error::InvalidWord)?;
+                        let old = mem::replace(&mut self.code[stub + 1], Op::Branch(ip as isize - (stub + 2) as isize));
+                        self.patches.push((stub + 1, old));
+                    }
+                    // The word is still in `calls` if its return stack is
+                    // unbalanced, so that errors report it
+                    let return_depth = match self.calls.last() {
+                        Some(frame) => frame.return_depth,
+                        None => break,
+                    };
+                    if self.return_stack.len() != return_depth {
+                        return Err(Error::ReturnStackImbalance);
+                    }
+                    ip = self.calls.pop().unwrap().ret;
+                },
+            }
+        }
+        Ok(())
+    }
+
+    pub(super) fn write(&mut self, bytes: &[u8]) -> ForthResult {
+        match self.output {
+            Output::Buffer(ref mut buf) => buf.extend_from_slice(bytes),
+            Output::Sink(ref mut sink) => sink.write_all(bytes).map_err(|e| Error::Io(e.kind()))?,
+        }
+        Ok(())
+    }
+
+    // The execution token on top of the stack, which is left there
+    fn check_xt(&self) -> Result<usize, Error> {
+        let xt = *self.stack.last().ok_or(Error::StackUnderflow)?;
+        xt.to_usize()
+            .filter(|xt| self.names.contains_key(xt))
+            .ok_or(Error::InvalidExecutionToken)
+    }
+
+    // Writes `n` spaces a chunk at a time, each counting as a step, so
+    // that large counts need no large buffer and obey the step limit
+    fn write_spaces(&mut self, mut n: usize) -> ForthResult {
+        const CHUNK: [u8; 256] = [b' '; 256];
+        while n > 0 {
+            count_step(&mut self.steps_left)?;
+            let len = n.min(CHUNK.len());
+            self.write(&CHUNK[..len])?;
+            n -= len;
+        }
+        Ok(())
+    }
+
+    pub(super) fn store(&mut self, addr: C, v: C) -> ForthResult {
+        let range = data_range(&self.data, addr, C::BYTES)?;
+        v.store(self.data_mut(range));
+        Ok(())
+    }
+
+    // Data space in `range`, to be changed
+    fn data_mut(&mut self, range: Range<usize>) -> &mut [u8] {
+        self.save_data(range.clone());
+        &mut self.data[range]
+    }
+
+    // Keeps the bytes in `range` that were there before the transactional
+    // eval running, if any. Bytes reserved since then need not be kept,
+    // unless ALLOT released some of the old ones first, and that saves them.
+    fn save_data(&mut self, range: Range<usize>) {
+        if let Some(ref mut undo) = self.undo {
+            let end = range.end.min(undo.data_len);
+            if range.start < end {
+                undo.data.push((range.start, self.data[range.start..end].to_vec()));
+            }
+        }
+    }
+}
+
+// Counts a step against the step limit. Only what can repeat counts: calls,
+// branches back and loops.
+#[inline]
+fn count_step(steps_left: &mut usize) -> ForthResult {
+    if *steps_left == 0 {
+        return Err(Error::StepLimitExceeded);
+    }
+    *steps_left -= 1;
+    Ok(())
+}
+
+fn jump(ip: usize, offset: isize) -> usize {
+    (ip as isize + offset) as usize
+}
+
+// Replaces the top two cells with `f(second, top)`, or leaves both in place
+#[inline]
+fn binary<C: Cell, F: FnOnce(C, C) -> Result<C, Error>>(stack: &mut Vec<C>, f: F) -> ForthResult {
+    let top = top_mut(stack, 2)?;
+    top[0] = f(top[0], top[1])?;
+    stack.pop();
+    Ok(())
+}
+
+#[inline]
+fn unary<C: Cell, F: FnOnce(C) -> Result<C, Error>>(stack: &mut [C], f: F) -> ForthResult {
+    let top = top_mut(stack, 1)?;
+    top[0] = f(top[0])?;
+    Ok(())
+}
+
+// Divides with a double-width intermediate, so neither the dividend of
+// FM/MOD and SM/REM nor the product of */ can overflow.
+fn eval_div<C: Cell>(stack: &mut Vec<C>, op: Op<C>, overflow: Overflow) -> ForthResult {
+    let arity = if op == Op::DivMod { 2 } else { 3 };
+    let args = top_mut(stack, arity)?.to_vec();
+
+    // Double-cell numbers keep the most significant cell on top of the stack
+    let ((lo, hi), divisor) = match op {
+        Op::DivMod => (sign_extend(args[0]), args[1]),
+        Op::StarSlash | Op::StarSlashMod => (args[0].mul_double(args[1]), args[2]),
+        _ => ((args[0], args[1]), args[2]),
+    };
+    if divisor == C::from(0) {
+        return Err(Error::DivisionByZero);
+    }
+
+    let (rem, quot) = C::div_double(lo, hi, divisor, op == Op::FmMod);
+    let quot = overflow.narrow(quot)?;
+
+    let len = stack.len();
+    stack.truncate(len - arity);
+    if op != Op::StarSlash {
+        stack.push(rem);
+    }
+    stack.push(quot);
+    Ok(())
+}
+
+fn sign_extend<C: Cell>(n: C) -> (C, C) {
+    (n, if n < C::from(0) { C::from(-1) } else { C::from(0) })
+}
+
+// Well-formed flags have either all bits set or none
+fn flag<C: Cell>(b: bool) -> C {
+    C::from(if b { -1 } else { 0 })
+}
+
+// The top `n` cells of the stack, deepest first
+#[inline]
+fn top_mut<C>(stack: &mut [C], n: usize) -> Result<&mut [C], Error> {
+    let len = stack.len();
+    if len < n { return Err(Error::StackUnderflow) };
+    Ok(&mut stack[len - n..])
+}
+
+// Pops the top two cells, topmost first, or neither of them
+fn pop_pair<C: Cell>(stack: &mut Vec<C>) -> Result<(C, C), Error> {
+    let (b, a) = {
+        let top = top_mut(stack, 2)?;
+        (top[0], top[1])
+    };
+    let len = stack.len();
+    stack.truncate(len - 2);
+    Ok((a, b))
+}
+
+// Validates the index on top of the stack against the cells beneath it
+fn pick_index<C: Cell>(stack: &[C]) -> Result<usize, Error> {
+    let u = stack.last().ok_or(Error::StackUnderflow)?;
+    u.to_usize().filter(|&u| u < stack.len() - 1).ok_or(Error::StackUnderflow)
+}
+
+// Advances the innermost loop, which repeats until the index crosses the
+// boundary between limit - 1 and limit in either direction.
+fn loop_step<C: Cell>(loop_stack: &mut Vec<LoopFrame<C>>, step: C) -> Result<bool, Error> {
+    let frame = loop_stack.last_mut().ok_or(Error::StackUnderflow)?;
+    let old_diff = frame.index.wrapping_sub(frame.limit);
+    let new_diff = old_diff.wrapping_add(step);
+    frame.index = frame.index.wrapping_add(step);
+
+    if (old_diff ^ new_diff) & (old_diff ^ step) < C::from(0) {
+        loop_stack.pop();
+        Ok(false)
+    } else {
+        Ok(true)
+    }
+}
+
+// The `n` bytes of the data space starting at `addr`
+fn data_range<C: Cell>(data: &[u8], addr: C, n: usize) -> Result<Range<usize>, Error> {
+    let start = addr.to_usize().ok_or(Error::InvalidAddress)?;
+    match start.checked_add(n) {
+        Some(end) if end <= data.len() => Ok(start..end),
+        _ => Err(Error::InvalidAddress),
+    }
+}
+
+fn to_return_stack<C: Cell>(stack: &mut Vec<C>, return_stack: &mut Vec<C>, n: usize) -> ForthResult {
+    top_mut(stack, n)?;
+    let len = stack.len();
+    return_stack.extend(stack.drain(len - n..));
+    Ok(())
+}
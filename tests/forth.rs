@@ -2,7 +2,10 @@
 
 extern crate forth;
 
-use forth::{Forth, Error};
+use forth::{Forth, Error, Overflow};
+
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
 
 #[test]
 fn no_input_no_stack() {
@@ -233,12 +236,45 @@ fn malformed_word_definition() {
     );
     assert_eq!(
         Err(Error::InvalidWord),
-        f.eval(": foo")
+        f.eval(": foo :")
+    );
+    assert_eq!(
+        Err(Error::InvalidWord),
+        f.eval(": foo 1 ; ;")
+    );
+}
+
+#[test]
+fn malformed_definition_keeps_previous_definition() {
+    let mut f = Forth::new();
+    f.eval(": foo 5 ;");
+    assert_eq!(
+        Err(Error::InvalidWord),
+        f.eval(": foo 1 :")
+    );
+    assert_eq!(
+        Err(Error::UnknownWord),
+        f.eval(": foo 1 bar ;")
+    );
+    assert_eq!(
+        Err(Error::InvalidWord),
+        f.eval(": foo 1 if ;")
     );
+    f.eval("foo");
+    assert_eq!("5", f.format_stack());
+}
+
+#[test]
+fn malformed_definition_keeps_built_in_word() {
+    let mut f = Forth::new();
     assert_eq!(
         Err(Error::InvalidWord),
-        f.eval(": foo 1")
+        f.eval(": swap :")
     );
+    f.eval(": swap");
+    f.abort_definition();
+    f.eval("1 2 swap");
+    assert_eq!("2 1", f.format_stack());
 }
 
 #[test]
@@ -249,3 +285,1799 @@ fn calling_non_existing_word() {
         f.eval("1 foo")
     );
 }
+
+#[test]
+fn if_then() {
+    let mut f = Forth::new();
+    f.eval(": foo if 10 then 20 ;");
+    f.eval("1 foo 0 foo");
+    assert_eq!("10 20 20", f.format_stack());
+}
+
+#[test]
+fn if_else_then() {
+    let mut f = Forth::new();
+    f.eval(": sign 0 swap - if 1 else 2 then ;");
+    f.eval("5 sign 0 sign");
+    assert_eq!("1 2", f.format_stack());
+}
+
+#[test]
+fn nested_if() {
+    let mut f = Forth::new();
+    f.eval(": foo if if 1 else 2 then else 3 then ;");
+    f.eval("1 1 foo 0 1 foo 0 foo");
+    assert_eq!("1 2 3", f.format_stack());
+}
+
+#[test]
+fn if_inside_called_word() {
+    let mut f = Forth::new();
+    f.eval(": foo if 1 else 2 then ;");
+    f.eval(": bar 7 swap foo 8 ;");
+    f.eval("0 bar 1 bar");
+    assert_eq!("7 2 8 7 1 8", f.format_stack());
+}
+
+#[test]
+fn if_error() {
+    let mut f = Forth::new();
+    f.eval(": foo if 1 then ;");
+    assert_eq!(
+        Err(Error::StackUnderflow),
+        f.eval("foo")
+    );
+}
+
+#[test]
+fn unbalanced_if() {
+    let mut f = Forth::new();
+    assert_eq!(
+        Err(Error::InvalidWord),
+        f.eval(": foo if 1 ;")
+    );
+    assert_eq!(
+        Err(Error::InvalidWord),
+        f.eval(": foo 1 then ;")
+    );
+    assert_eq!(
+        Err(Error::InvalidWord),
+        f.eval(": foo else 1 then ;")
+    );
+    assert_eq!(
+        Err(Error::InvalidWord),
+        f.eval(": foo if 1 else 2 else 3 then ;")
+    );
+}
+
+#[test]
+fn if_is_compile_only() {
+    let mut f = Forth::new();
+    assert_eq!(
+        Err(Error::InvalidWord),
+        f.eval("1 if 2 then")
+    );
+}
+
+#[test]
+fn do_loop() {
+    let mut f = Forth::new();
+    f.eval(": foo 5 0 do i loop ;");
+    f.eval("foo");
+    assert_eq!("0 1 2 3 4", f.format_stack());
+}
+
+#[test]
+fn do_loop_runs_at_least_once() {
+    let mut f = Forth::new();
+    f.eval(": foo 0 do 1 leave loop ;");
+    f.eval("0 foo");
+    assert_eq!("1", f.format_stack());
+}
+
+#[test]
+fn question_do_skips_empty_range() {
+    let mut f = Forth::new();
+    f.eval(": foo 7 swap 0 ?do i loop 8 ;");
+    f.eval("0 foo 2 foo");
+    assert_eq!("7 8 7 0 1 8", f.format_stack());
+}
+
+#[test]
+fn nested_loops_i_and_j() {
+    let mut f = Forth::new();
+    f.eval(": foo 3 1 do 2 0 do j i loop loop ;");
+    f.eval("foo");
+    assert_eq!("1 0 1 1 2 0 2 1", f.format_stack());
+}
+
+#[test]
+fn plus_loop() {
+    let mut f = Forth::new();
+    f.eval(": foo 10 0 do i 3 +loop ;");
+    f.eval("foo");
+    assert_eq!("0 3 6 9", f.format_stack());
+}
+
+#[test]
+fn plus_loop_negative_increment() {
+    let mut f = Forth::new();
+    f.eval(": foo 0 4 do i -1 +loop ;");
+    f.eval("foo");
+    assert_eq!("4 3 2 1 0", f.format_stack());
+}
+
+#[test]
+fn plus_loop_crosses_boundary() {
+    let mut f = Forth::new();
+    f.eval(": foo -3 3 do i -2 +loop ;");
+    f.eval("foo");
+    assert_eq!("3 1 -1 -3", f.format_stack());
+}
+
+#[test]
+fn leave_exits_innermost_loop() {
+    let mut f = Forth::new();
+    f.eval(": foo 3 0 do 10 0 do i j - if i else leave then loop loop ;");
+    f.eval("foo");
+    assert_eq!("0 0 1", f.format_stack());
+}
+
+#[test]
+fn unloop_exit_leaves_loop_early() {
+    let mut f = Forth::new();
+    f.eval(": lp 10 0 do i 3 = if unloop exit then i loop ;");
+    f.eval(": outer 2 0 do lp 100 loop ;");
+    f.eval("lp outer");
+    assert_eq!("0 1 2 0 1 2 100 0 1 2 100", f.format_stack());
+}
+
+#[test]
+fn exit_returns_from_word() {
+    let mut f = Forth::new();
+    f.eval(": foo 1 exit 2 ; foo");
+    assert_eq!("1", f.format_stack());
+}
+
+#[test]
+fn unloop_discards_loop_parameters() {
+    let mut f = Forth::new();
+    f.eval(": foo 10 0 do unloop i loop ;");
+    assert_eq!(
+        Err(Error::StackUnderflow),
+        f.eval("foo")
+    );
+}
+
+#[test]
+fn loop_error() {
+    let mut f = Forth::new();
+    f.eval(": foo do i loop ;");
+    assert_eq!(
+        Err(Error::StackUnderflow),
+        f.eval("1 foo")
+    );
+    assert_eq!(
+        Err(Error::StackUnderflow),
+        f.eval("i")
+    );
+}
+
+#[test]
+fn unbalanced_loop() {
+    let mut f = Forth::new();
+    assert_eq!(
+        Err(Error::InvalidWord),
+        f.eval(": foo 10 0 do i ;")
+    );
+    assert_eq!(
+        Err(Error::InvalidWord),
+        f.eval(": foo i loop ;")
+    );
+    assert_eq!(
+        Err(Error::InvalidWord),
+        f.eval(": foo leave ;")
+    );
+    assert_eq!(
+        Err(Error::InvalidWord),
+        f.eval(": foo 10 0 do if loop then ;")
+    );
+    assert_eq!(
+        Err(Error::InvalidWord),
+        f.eval("10 0 do i loop")
+    );
+}
+
+#[test]
+fn begin_until() {
+    let mut f = Forth::new();
+    f.eval(": countdown begin dup 1 - dup if 0 else 1 then until ;");
+    f.eval("3 countdown");
+    assert_eq!("3 2 1 0", f.format_stack());
+}
+
+#[test]
+fn begin_while_repeat() {
+    let mut f = Forth::new();
+    f.eval(": countdown begin dup while dup 1 - repeat ;");
+    f.eval("3 countdown 0 countdown");
+    assert_eq!("3 2 1 0 0", f.format_stack());
+}
+
+#[test]
+fn begin_again_with_leave_through_do() {
+    let mut f = Forth::new();
+    f.eval(": foo 3 0 do begin i leave again loop ;");
+    f.eval("foo");
+    assert_eq!("0", f.format_stack());
+}
+
+#[test]
+fn until_error() {
+    let mut f = Forth::new();
+    f.eval(": foo begin until ;");
+    assert_eq!(
+        Err(Error::StackUnderflow),
+        f.eval("foo")
+    );
+}
+
+#[test]
+fn unbalanced_begin() {
+    let mut f = Forth::new();
+    assert_eq!(
+        Err(Error::InvalidWord),
+        f.eval(": foo begin 1 ;")
+    );
+    assert_eq!(
+        Err(Error::InvalidWord),
+        f.eval(": foo 1 until ;")
+    );
+    assert_eq!(
+        Err(Error::InvalidWord),
+        f.eval(": foo begin 1 while again ;")
+    );
+    assert_eq!(
+        Err(Error::InvalidWord),
+        f.eval(": foo if begin then again ;")
+    );
+    assert_eq!(
+        Err(Error::InvalidWord),
+        f.eval("begin 1 until")
+    );
+}
+
+#[test]
+fn step_limit_interrupts_infinite_loop() {
+    let mut f = Forth::new();
+    f.set_step_limit(Some(1000));
+    f.eval(": forever begin again ;");
+    assert_eq!(
+        Err(Error::StepLimitExceeded),
+        f.eval("forever")
+    );
+    assert_eq!(Ok(()), f.eval("1 2 +"));
+    assert_eq!("3", f.format_stack());
+}
+
+#[test]
+fn comparisons() {
+    let mut f = Forth::new();
+    f.eval("1 1 = 1 2 = 1 2 <> 2 2 <>");
+    assert_eq!("-1 0 -1 0", f.format_stack());
+    let mut f = Forth::new();
+    f.eval("1 2 < 2 1 < -1 1 > 1 -1 >");
+    assert_eq!("-1 0 0 -1", f.format_stack());
+}
+
+#[test]
+fn unsigned_comparison() {
+    let mut f = Forth::new();
+    f.eval("1 -1 u< -1 1 u<");
+    assert_eq!("-1 0", f.format_stack());
+}
+
+#[test]
+fn comparisons_with_zero() {
+    let mut f = Forth::new();
+    f.eval("0 0= 5 0= -5 0< 5 0< 5 0> -5 0>");
+    assert_eq!("-1 0 -1 0 -1 0", f.format_stack());
+}
+
+#[test]
+fn true_and_false() {
+    let mut f = Forth::new();
+    f.eval("true false");
+    assert_eq!("-1 0", f.format_stack());
+}
+
+#[test]
+fn bitwise_logic() {
+    let mut f = Forth::new();
+    f.eval("12 10 and 12 10 or 12 10 xor 0 invert");
+    assert_eq!("8 14 6 -1", f.format_stack());
+}
+
+#[test]
+fn shifts() {
+    let mut f = Forth::new();
+    f.eval("1 4 lshift 256 4 rshift -1 28 rshift 1 32 lshift");
+    assert_eq!("16 16 15 0", f.format_stack());
+}
+
+#[test]
+fn comparison_drives_conditional() {
+    let mut f = Forth::new();
+    f.eval(": max2 over over < if swap then drop ;");
+    f.eval("3 7 max2 9 2 max2");
+    assert_eq!("7 9", f.format_stack());
+}
+
+#[test]
+fn comparison_error() {
+    let mut f = Forth::new();
+    assert_eq!(
+        Err(Error::StackUnderflow),
+        f.eval("1 =")
+    );
+    let mut f = Forth::new();
+    assert_eq!(
+        Err(Error::StackUnderflow),
+        f.eval("0=")
+    );
+}
+
+#[test]
+fn mod_and_div_mod() {
+    let mut f = Forth::new();
+    f.eval("7 3 mod -7 3 mod 7 3 /mod");
+    assert_eq!("1 -1 1 2", f.format_stack());
+}
+
+#[test]
+fn star_slash_uses_double_width_product() {
+    let mut f = Forth::new();
+    f.eval("1000000 1000000 1000000 */ 100000 3 7 */mod");
+    assert_eq!("1000000 1 42857", f.format_stack());
+}
+
+#[test]
+fn floored_and_symmetric_division() {
+    let mut f = Forth::new();
+    f.eval("-7 -1 2 fm/mod -7 -1 2 sm/rem");
+    assert_eq!("1 -4 -1 -3", f.format_stack());
+    let mut f = Forth::new();
+    f.eval("7 0 -2 fm/mod 7 0 -2 sm/rem");
+    assert_eq!("-1 -4 1 -3", f.format_stack());
+}
+
+#[test]
+fn double_dividend() {
+    let mut f = Forth::new();
+    // 2^32 + 2
+    f.eval("2 1 2 sm/rem");
+    assert_eq!("0 -2147483647", f.format_stack());
+}
+
+#[test]
+fn unary_arithmetic() {
+    let mut f = Forth::new();
+    f.eval("5 negate -5 abs 5 1+ 5 1- 5 2* -5 2/");
+    assert_eq!("-5 5 6 4 10 -3", f.format_stack());
+}
+
+#[test]
+fn min_and_max() {
+    let mut f = Forth::new();
+    f.eval("3 -4 min 3 -4 max");
+    assert_eq!("-4 3", f.format_stack());
+}
+
+#[test]
+fn division_by_zero_in_all_division_words() {
+    let mut f = Forth::new();
+    for input in &["1 0 mod", "1 0 /mod", "1 2 0 */", "1 2 0 */mod", "1 0 0 fm/mod", "1 0 0 sm/rem"] {
+        assert_eq!(
+            Err(Error::DivisionByZero),
+            f.eval(input)
+        );
+    }
+}
+
+#[test]
+fn arithmetic_word_errors() {
+    let mut f = Forth::new();
+    assert_eq!(
+        Err(Error::StackUnderflow),
+        f.eval("negate")
+    );
+    assert_eq!(
+        Err(Error::StackUnderflow),
+        f.eval("1 2 */")
+    );
+    let mut f = Forth::new();
+    assert_eq!(
+        Err(Error::StackUnderflow),
+        f.eval("1 /mod")
+    );
+}
+
+#[test]
+fn overflow_wraps_by_default() {
+    let mut f = Forth::new();
+    f.eval("2147483647 1 + -2147483648 -1 / -2147483648 negate 65536 65536 *");
+    assert_eq!("-2147483648 -2147483648 -2147483648 0", f.format_stack());
+}
+
+#[test]
+fn checked_overflow() {
+    for input in &["2147483647 1 +", "-2147483648 1 -", "65536 65536 *",
+                   "-2147483648 -1 /", "-2147483648 abs", "2147483647 1+",
+                   "2147483647 2*", "-2147483648 2*", "2147483647 2 1 */", "0 1 1 sm/rem", "0 -2147483648 -1 fm/mod"] {
+        let mut f = Forth::with_overflow(Overflow::Checked);
+        assert_eq!(
+            Err(Error::Overflow),
+            f.eval(input)
+        );
+    }
+    let mut f = Forth::with_overflow(Overflow::Checked);
+    f.eval("variable v 2147483647 v !");
+    assert_eq!(
+        Err(Error::Overflow),
+        f.eval("1 v +!")
+    );
+    f.eval("v @");
+    assert_eq!("1 0 2147483647", f.format_stack());
+    let mut f = Forth::with_overflow(Overflow::Checked);
+    assert_eq!(Ok(()), f.eval("-2147483648 -1 mod 2147483647 2 2 */"));
+    assert_eq!("0 2147483647", f.format_stack());
+}
+
+#[test]
+fn saturating_overflow() {
+    let mut f = Forth::with_overflow(Overflow::Saturating);
+    f.eval("2147483647 1 + -2147483647 10 - -2147483648 -1 / 0 -2147483648 -1 sm/rem");
+    assert_eq!("2147483647 -2147483648 2147483647 0 2147483647", f.format_stack());
+    let mut f = Forth::with_overflow(Overflow::Saturating);
+    f.eval("variable v 2147483647 v ! 5 v +! v @ -1073741825 2*");
+    assert_eq!("2147483647 -2147483648", f.format_stack());
+}
+
+#[test]
+fn sixty_four_bit_cells() {
+    let mut f = Forth::<i64>::default();
+    f.eval("1700000000000 1000 * 2147483647 1 +");
+    assert_eq!("1700000000000000 2147483648", f.format_stack());
+}
+
+#[test]
+fn sixteen_bit_cells_wrap() {
+    let mut f = Forth::<i16>::default();
+    f.eval("32767 1 + -1 1 rshift 300 300 100 */");
+    assert_eq!("-32768 32767 900", f.format_stack());
+}
+
+#[test]
+fn sixteen_bit_literal_out_of_range() {
+    let mut f = Forth::<i16>::default();
+    assert_eq!(
+        Err(Error::UnknownWord),
+        f.eval("40000")
+    );
+}
+
+#[test]
+fn checked_overflow_with_generic_cells() {
+    let mut f = Forth::<i64>::default();
+    f.set_overflow(Overflow::Checked);
+    assert_eq!(
+        Err(Error::Overflow),
+        f.eval("9223372036854775807 1 +")
+    );
+}
+
+#[test]
+fn one_hundred_twenty_eight_bit_double_width_division() {
+    let mut f = Forth::<i128>::default();
+    // (2^126 * 6) / 4 needs a 256-bit intermediate product
+    f.eval("1 126 lshift 6 4 */ 1 125 lshift 3 * =");
+    f.eval("-7 -1 2 fm/mod -7 -1 2 sm/rem 7 0 -2 fm/mod");
+    assert_eq!("-1 1 -4 -1 -3 -1 -4", f.format_stack());
+}
+
+#[test]
+fn one_hundred_twenty_eight_bit_overflow() {
+    let mut f = Forth::<i128>::default();
+    f.set_overflow(Overflow::Saturating);
+    f.eval("0 1 1 sm/rem 0 -1 1 fm/mod 170141183460469231731687303715884105727 dup *");
+    assert_eq!(
+        "0 170141183460469231731687303715884105727 0 -170141183460469231731687303715884105728 170141183460469231731687303715884105727",
+        f.format_stack()
+    );
+}
+
+#[test]
+fn rot_and_minus_rot() {
+    let mut f = Forth::new();
+    f.eval("1 2 3 rot 4 5 6 -rot");
+    assert_eq!("2 3 1 6 4 5", f.format_stack());
+}
+
+#[test]
+fn nip_and_tuck() {
+    let mut f = Forth::new();
+    f.eval("1 2 nip 3 tuck");
+    assert_eq!("3 2 3", f.format_stack());
+}
+
+#[test]
+fn pick() {
+    let mut f = Forth::new();
+    f.eval("10 20 30 0 pick 2 pick");
+    assert_eq!("10 20 30 30 20", f.format_stack());
+}
+
+#[test]
+fn roll() {
+    let mut f = Forth::new();
+    f.eval("10 20 30 40 3 roll 0 roll");
+    assert_eq!("20 30 40 10", f.format_stack());
+}
+
+#[test]
+fn question_dup() {
+    let mut f = Forth::new();
+    f.eval("0 ?dup 5 ?dup");
+    assert_eq!("0 5 5", f.format_stack());
+}
+
+#[test]
+fn depth() {
+    let mut f = Forth::new();
+    f.eval("depth 7 7 depth");
+    assert_eq!("0 7 7 3", f.format_stack());
+}
+
+#[test]
+fn double_cell_stack_words() {
+    let mut f = Forth::new();
+    f.eval("1 2 2dup 3 4 2swap 2drop");
+    assert_eq!("1 2 3 4", f.format_stack());
+    f.eval("2over 5 6 2rot");
+    assert_eq!("1 2 1 2 5 6 3 4", f.format_stack());
+}
+
+#[test]
+fn pick_and_roll_errors() {
+    let mut f = Forth::new();
+    for input in &["pick", "0 pick", "1 2 pick", "1 -1 pick", "1 2 roll", "1 -1 roll"] {
+        assert_eq!(
+            Err(Error::StackUnderflow),
+            f.eval(input)
+        );
+        f = Forth::new();
+    }
+}
+
+#[test]
+fn extended_stack_word_errors() {
+    for input in &["1 2 rot", "1 2 -rot", "1 nip", "1 tuck", "?dup", "1 2dup",
+                   "1 2drop", "1 2 3 2swap", "1 2 3 2over", "1 2 3 4 5 2rot"] {
+        let mut f = Forth::new();
+        assert_eq!(
+            Err(Error::StackUnderflow),
+            f.eval(input)
+        );
+        assert_eq!(Ok(()), f.eval("depth"));
+    }
+}
+
+#[test]
+fn return_stack_transfer() {
+    let mut f = Forth::new();
+    f.eval(": foo >r 10 r@ r> ;");
+    f.eval("1 2 foo");
+    assert_eq!("1 10 2 2", f.format_stack());
+    assert!(f.return_stack().is_empty());
+}
+
+#[test]
+fn double_return_stack_transfer() {
+    let mut f = Forth::new();
+    f.eval(": foo 2>r 3 2r> ;");
+    f.eval("1 2 foo");
+    assert_eq!("3 1 2", f.format_stack());
+}
+
+#[test]
+fn return_stack_spans_loops() {
+    let mut f = Forth::new();
+    f.eval(": sum 0 >r 0 do i r> + >r loop r> ;");
+    f.eval("5 sum");
+    assert_eq!("10", f.format_stack());
+}
+
+#[test]
+fn return_stack_left_unbalanced_by_definition() {
+    let mut f = Forth::new();
+    f.eval(": foo >r ;");
+    assert_eq!(
+        Err(Error::ReturnStackImbalance),
+        f.eval("1 foo")
+    );
+    assert_eq!(&[1], f.return_stack());
+}
+
+#[test]
+fn return_stack_underflow() {
+    let mut f = Forth::new();
+    assert_eq!(
+        Err(Error::ReturnStackImbalance),
+        f.eval("r>")
+    );
+    f.eval(": bar r> ;");
+    f.eval(": foo >r bar ;");
+    assert_eq!(
+        Err(Error::ReturnStackImbalance),
+        f.eval("1 foo")
+    );
+    assert_eq!(
+        Err(Error::StackUnderflow),
+        f.eval("1 2>r")
+    );
+}
+
+#[test]
+fn return_stack_left_unbalanced_by_input() {
+    let mut f = Forth::new();
+    assert_eq!(
+        Err(Error::ReturnStackImbalance),
+        f.eval("1 >r")
+    );
+    assert_eq!(Ok(()), f.eval("1 >r r>"));
+    assert_eq!("1", f.format_stack());
+}
+
+#[test]
+fn failing_word_keeps_its_operands() {
+    let mut f = Forth::new();
+    assert_eq!(
+        Err(Error::StackUnderflow),
+        f.eval("1 +")
+    );
+    assert_eq!("1", f.format_stack());
+    assert_eq!(
+        Err(Error::DivisionByZero),
+        f.eval("0 /")
+    );
+    assert_eq!("1 0", f.format_stack());
+
+    // Words built from a literal and another operation
+    for word in &["1+", "1-", "2*", "0=", "0<", "0>", "invert", "cells", "cell+"] {
+        let mut f = Forth::new();
+        assert_eq!(
+            Err(Error::StackUnderflow),
+            f.eval(word)
+        );
+        assert_eq!("", f.format_stack());
+    }
+    let mut f = Forth::new();
+    assert_eq!(
+        Err(Error::StackUnderflow),
+        f.eval("1 erase")
+    );
+    assert_eq!("1", f.format_stack());
+    let mut f = Forth::with_overflow(Overflow::Checked);
+    assert_eq!(
+        Err(Error::Overflow),
+        f.eval("2147483647 1+")
+    );
+    assert_eq!("2147483647", f.format_stack());
+    let mut f = Forth::new();
+    f.set_output(BrokenSink);
+    assert_eq!(
+        Err(Error::Io(io::ErrorKind::BrokenPipe)),
+        f.eval("1 cr")
+    );
+    assert_eq!("1", f.format_stack());
+}
+
+#[test]
+fn failing_eval_is_not_atomic_by_default() {
+    let mut f = Forth::new();
+    assert_eq!(
+        Err(Error::DivisionByZero),
+        f.eval("1 2 3 0 / 5")
+    );
+    assert_eq!("1 2 3 0", f.format_stack());
+}
+
+#[test]
+fn transactional_eval_restores_stack() {
+    let mut f = Forth::new();
+    f.set_transactional(true);
+    f.eval("1 2");
+    assert_eq!(
+        Err(Error::DivisionByZero),
+        f.eval("3 0 / 5")
+    );
+    assert_eq!("1 2", f.format_stack());
+    assert_eq!(
+        Err(Error::StackUnderflow),
+        f.eval("drop drop drop")
+    );
+    assert_eq!("1 2", f.format_stack());
+}
+
+#[test]
+fn transactional_eval_restores_definitions() {
+    let mut f = Forth::new();
+    f.set_transactional(true);
+    f.eval(": foo 1 ;");
+    assert_eq!(
+        Err(Error::UnknownWord),
+        f.eval(": foo 2 ; : bar 3 ; baz")
+    );
+    assert_eq!(
+        Err(Error::UnknownWord),
+        f.eval("bar")
+    );
+    f.eval("foo");
+    assert_eq!("1", f.format_stack());
+}
+
+#[test]
+fn transactional_eval_keeps_successful_changes() {
+    let mut f = Forth::new();
+    f.set_transactional(true);
+    f.eval(": foo 1 ; foo foo");
+    assert_eq!("1 1", f.format_stack());
+}
+
+#[test]
+fn transactional_eval_restores_does() {
+    let mut f = Forth::new();
+    f.set_transactional(true);
+    assert_eq!(Ok(()), f.eval(": setd does> drop 42 ; create aa 7 , aa @"));
+    assert_eq!(
+        Err(Error::DivisionByZero),
+        f.eval("setd 1 0 /")
+    );
+    f.eval("aa @");
+    assert_eq!("7 7", f.format_stack());
+}
+
+#[test]
+fn transactional_eval_restores_data_space() {
+    let mut f = Forth::new();
+    f.set_transactional(true);
+    f.eval("create a 1 , 2 , 3 , here");
+    assert_eq!(
+        Err(Error::DivisionByZero),
+        f.eval("9 a ! a cell+ 1 255 fill -2 cells allot 5 , 6 , 7 , a a 2 cells + 1 cells move 1 0 /")
+    );
+    f.eval("here = a @ a cell+ @ a 2 cells + @");
+    assert_eq!("-1 1 2 3", f.format_stack());
+}
+
+#[test]
+fn definitions_keep_the_meaning_they_were_compiled_with() {
+    let mut f = Forth::new();
+    f.eval(": foo 5 ;");
+    f.eval(": bar foo ;");
+    f.eval(": foo 6 ;");
+    f.eval("bar foo");
+    assert_eq!("5 6", f.format_stack());
+}
+
+#[test]
+fn deeply_layered_definitions() {
+    let mut f = Forth::new();
+    f.eval(": w0 1 ;");
+    for n in 1..100 {
+        assert_eq!(Ok(()), f.eval(&format!(": w{} w{} w{} ;", n, n - 1, n - 1)));
+    }
+    f.eval("w3");
+    assert_eq!("1 1 1 1 1 1 1 1", f.format_stack());
+}
+
+#[test]
+fn loop_indices_are_visible_in_called_words() {
+    let mut f = Forth::new();
+    f.eval(": cell j 10 * i + ; : table 3 1 do 3 1 do cell loop loop ;");
+    f.eval("table");
+    assert_eq!("11 12 21 22", f.format_stack());
+}
+
+#[test]
+fn definitions_interleaved_with_input() {
+    let mut f = Forth::new();
+    f.eval("1 : foo 2 ; foo : bar foo 3 ; bar 4 : baz bar bar ; baz");
+    assert_eq!("1 2 2 3 4 2 3 2 3", f.format_stack());
+}
+
+#[test]
+fn recurse() {
+    let mut f = Forth::new();
+    f.eval(": fact dup 1 > if dup 1- recurse * then ;");
+    f.eval("5 fact 10 fact");
+    assert_eq!("120 3628800", f.format_stack());
+}
+
+#[test]
+fn word_is_hidden_until_its_definition_ends() {
+    let mut f = Forth::new();
+    f.eval(": foo 5 ;");
+    f.eval(": foo foo 1+ ;");
+    f.eval("foo");
+    assert_eq!("6", f.format_stack());
+}
+
+#[test]
+fn new_word_cannot_refer_to_itself_by_name() {
+    let mut f = Forth::new();
+    assert_eq!(
+        Err(Error::UnknownWord),
+        f.eval(": foo foo ;")
+    );
+}
+
+#[test]
+fn recurse_outside_definition() {
+    let mut f = Forth::new();
+    assert_eq!(
+        Err(Error::InvalidWord),
+        f.eval("recurse")
+    );
+}
+
+#[test]
+fn runaway_recursion_exceeds_call_depth() {
+    let mut f = Forth::new();
+    f.eval(": forever recurse ;");
+    assert_eq!(
+        Err(Error::CallDepthExceeded),
+        f.eval("forever")
+    );
+}
+
+#[test]
+fn max_call_depth() {
+    let mut f = Forth::new();
+    f.set_max_call_depth(10);
+    f.eval(": countdown dup if 1- recurse then ;");
+    assert_eq!(Ok(()), f.eval("9 countdown"));
+    assert_eq!(
+        Err(Error::CallDepthExceeded),
+        f.eval("10 countdown")
+    );
+}
+
+#[test]
+fn definition_spanning_several_evals() {
+    let mut f = Forth::new();
+    assert_eq!(Ok(()), f.eval("1 : foo"));
+    assert!(f.is_compiling());
+    assert_eq!(Ok(()), f.eval("2 3"));
+    assert!(f.is_compiling());
+    assert_eq!(Ok(()), f.eval("+ ; foo"));
+    assert!(!f.is_compiling());
+    assert_eq!("1 5", f.format_stack());
+}
+
+#[test]
+fn control_structure_spanning_several_evals() {
+    let mut f = Forth::new();
+    f.eval(": sum 0 swap 0 do");
+    f.eval("  i +");
+    f.eval("loop ;");
+    f.eval("5 sum");
+    assert_eq!("10", f.format_stack());
+}
+
+#[test]
+fn abort_definition() {
+    let mut f = Forth::new();
+    f.eval(": foo 1 ;");
+    f.eval(": foo 2");
+    f.abort_definition();
+    assert!(!f.is_compiling());
+    f.eval("foo");
+    assert_eq!("1", f.format_stack());
+}
+
+#[test]
+fn failing_eval_aborts_definition() {
+    let mut f = Forth::new();
+    f.eval(": foo 1");
+    assert_eq!(
+        Err(Error::UnknownWord),
+        f.eval("bar")
+    );
+    assert!(!f.is_compiling());
+    assert_eq!(
+        Err(Error::UnknownWord),
+        f.eval("foo")
+    );
+}
+
+#[test]
+fn variables() {
+    let mut f = Forth::new();
+    f.eval("variable x variable y");
+    f.eval("5 x ! 7 y ! x @ y @ 3 x +! x @");
+    assert_eq!("5 7 8", f.format_stack());
+}
+
+#[test]
+fn variables_in_definitions() {
+    let mut f = Forth::new();
+    f.eval("variable count");
+    f.eval(": bump 1 count +! ; bump bump bump count @");
+    assert_eq!("3", f.format_stack());
+}
+
+#[test]
+fn new_variable_starts_at_zero() {
+    let mut f = Forth::new();
+    f.eval("variable x x @");
+    assert_eq!("0", f.format_stack());
+}
+
+#[test]
+fn constants() {
+    let mut f = Forth::new();
+    f.eval("6 7 * constant answer : twice answer 2 * ; answer twice");
+    assert_eq!("42 84", f.format_stack());
+}
+
+#[test]
+fn constant_needs_a_value() {
+    let mut f = Forth::new();
+    assert_eq!(
+        Err(Error::StackUnderflow),
+        f.eval("constant foo")
+    );
+}
+
+#[test]
+fn values() {
+    let mut f = Forth::new();
+    f.eval("10 value limit limit");
+    f.eval("20 to limit limit");
+    f.eval(": reset 0 to limit ; reset limit");
+    assert_eq!("10 20 0", f.format_stack());
+}
+
+#[test]
+fn to_only_changes_values() {
+    let mut f = Forth::new();
+    f.eval("1 constant one");
+    assert_eq!(
+        Err(Error::InvalidWord),
+        f.eval("2 to one")
+    );
+    assert_eq!(
+        Err(Error::UnknownWord),
+        f.eval("2 to two")
+    );
+}
+
+#[test]
+fn defining_words_in_definitions() {
+    let mut f = Forth::new();
+    f.eval(": counter variable ; : five 5 constant ;");
+    f.eval("counter hits five hand 3 hits ! hits @ hand");
+    assert_eq!("3 5", f.format_stack());
+}
+
+#[test]
+fn defining_word_without_name() {
+    let mut f = Forth::new();
+    assert_eq!(
+        Err(Error::InvalidWord),
+        f.eval("variable")
+    );
+    f.eval(": counter variable ;");
+    assert_eq!(
+        Err(Error::InvalidWord),
+        f.eval("counter")
+    );
+}
+
+#[test]
+fn invalid_address() {
+    let mut f = Forth::new();
+    f.eval("variable x");
+    assert_eq!(
+        Err(Error::InvalidAddress),
+        f.eval("x 1+ @")
+    );
+    assert_eq!(
+        Err(Error::InvalidAddress),
+        f.eval("5 -1 !")
+    );
+}
+
+#[test]
+fn create_table() {
+    let mut f = Forth::new();
+    f.eval("create table 1 , 2 , 3 ,");
+    f.eval("table @ table 2 cells + @ table cell+ @");
+    assert_eq!("1 3 2", f.format_stack());
+}
+
+#[test]
+fn allot_moves_here() {
+    let mut f = Forth::new();
+    f.eval("create buf 10 cells allot here buf - 1 cells /");
+    assert_eq!("10", f.format_stack());
+    f.eval("-4 cells allot here buf - 1 cells /");
+    assert_eq!("10 6", f.format_stack());
+}
+
+#[test]
+fn byte_access() {
+    let mut f = Forth::new();
+    f.eval("create s 72 c, 105 c, 300 c,");
+    f.eval("s c@ s 1 chars + c@ s 2 + c@");
+    f.eval("33 s c! s c@");
+    assert_eq!("72 105 44 33", f.format_stack());
+}
+
+#[test]
+fn cell_size_follows_cell_type() {
+    let mut f = Forth::<i16>::default();
+    f.eval("1 cells 3 cell+");
+    assert_eq!("2 5", f.format_stack());
+}
+
+#[test]
+fn align() {
+    let mut f = Forth::new();
+    f.eval("1 aligned 4 aligned 5 aligned 0 aligned");
+    f.eval("here 1 allot align here swap -");
+    assert_eq!("4 4 8 0 4", f.format_stack());
+}
+
+#[test]
+fn fill_erase_and_move() {
+    let mut f = Forth::new();
+    f.eval("create a 1 , 2 , 3 , create b 3 cells allot");
+    f.eval("a b 3 cells move b @ b cell+ @ b 2 cells + @");
+    f.eval("b 3 cells 255 fill b c@ b 2 cells + 3 + c@");
+    f.eval("b 3 cells erase b cell+ @");
+    assert_eq!("1 2 3 255 255 0", f.format_stack());
+}
+
+#[test]
+fn zero_count_fill_erase_and_move_do_nothing() {
+    let mut f = Forth::new();
+    assert_eq!(Ok(()), f.eval("1000 0 0 fill -5 0 erase 1000 2000 0 move"));
+    assert_eq!("", f.format_stack());
+}
+
+#[test]
+fn variables_live_in_data_space() {
+    let mut f = Forth::new();
+    f.eval("variable x here x - 7 x ! x @");
+    assert_eq!("4 7", f.format_stack());
+}
+
+#[test]
+fn data_space_bounds() {
+    let mut f = Forth::new();
+    f.eval("create buf 2 cells allot");
+    assert_eq!(
+        Err(Error::InvalidAddress),
+        f.eval("buf 2 cells + @")
+    );
+    assert_eq!(
+        Err(Error::InvalidAddress),
+        f.eval("buf 2 cells 1+ 0 fill")
+    );
+    assert_eq!(
+        Err(Error::InvalidAddress),
+        f.eval("buf buf 1+ 2 cells move")
+    );
+    assert_eq!(
+        Err(Error::InvalidAddress),
+        f.eval("-100 allot")
+    );
+}
+
+#[test]
+fn max_data_space() {
+    let mut f = Forth::new();
+    f.set_max_data_space(16);
+    assert_eq!(Ok(()), f.eval("create buf 3 cells allot 1 ,"));
+    assert_eq!(
+        Err(Error::DataSpaceOverflow),
+        f.eval("1 c,")
+    );
+    assert_eq!(
+        Err(Error::DataSpaceOverflow),
+        f.eval("variable x")
+    );
+}
+
+#[test]
+fn data_space_addresses_fit_in_a_cell() {
+    let mut f = Forth::<i16>::default();
+    assert_eq!(Ok(()), f.eval("20000 allot"));
+    assert_eq!(
+        Err(Error::DataSpaceOverflow),
+        f.eval("20000 allot")
+    );
+    assert_eq!(Ok(()), f.eval("drop 12767 allot here"));
+    assert_eq!("32767", f.format_stack());
+    assert_eq!(
+        Err(Error::DataSpaceOverflow),
+        f.eval("1 c,")
+    );
+    assert_eq!(Ok(()), f.eval("drop -8 allot variable zz 5 zz ! zz @"));
+    assert_eq!("32767 5", f.format_stack());
+}
+
+#[test]
+fn create_does() {
+    let mut f = Forth::new();
+    f.eval(": array create cells allot does> swap cells + ;");
+    f.eval("3 array a 2 array b");
+    f.eval("10 0 a ! 20 2 a ! 30 1 b !");
+    f.eval("0 a @ 2 a @ 1 b @");
+    assert_eq!("10 20 30", f.format_stack());
+}
+
+#[test]
+fn does_with_data_from_comma() {
+    let mut f = Forth::new();
+    f.eval(": const create , does> @ ;");
+    f.eval("5 const five 7 const seven : sum five seven + ; sum");
+    assert_eq!("12", f.format_stack());
+}
+
+#[test]
+fn does_changes_tokens_taken_before_it() {
+    let mut f = Forth::new();
+    f.eval(": labelled does> @ 100 + ;");
+    f.eval("create x 1 , ' x labelled execute");
+    assert_eq!("101", f.format_stack());
+}
+
+#[test]
+fn does_is_compile_only() {
+    let mut f = Forth::new();
+    assert_eq!(
+        Err(Error::InvalidWord),
+        f.eval("create x does>")
+    );
+}
+
+#[test]
+fn does_only_changes_the_latest_definition() {
+    let mut f = Forth::new();
+    f.eval(": const create , does> @ ; 7 const seven : k does> ;");
+    assert_eq!(
+        Err(Error::InvalidWord),
+        f.eval("k")
+    );
+    f.eval("seven");
+    assert_eq!("7", f.format_stack());
+    f.eval("create x 1 , : y does> 2 ; variable z");
+    assert_eq!(
+        Err(Error::InvalidWord),
+        f.eval("y")
+    );
+    f.eval("x @");
+    assert_eq!("7 1", f.format_stack());
+}
+
+#[test]
+fn to_body() {
+    let mut f = Forth::new();
+    f.eval("create x 7 , ' x >body @ ' x >body x =");
+    assert_eq!("7 -1", f.format_stack());
+    assert_eq!(
+        Err(Error::InvalidWord),
+        f.eval("' dup >body")
+    );
+    assert_eq!(
+        Err(Error::InvalidWord),
+        f.eval(": five 5 ; ' five >body")
+    );
+    assert_eq!(
+        Err(Error::InvalidWord),
+        f.eval("10 constant ten ' ten >body")
+    );
+    assert_eq!(
+        Err(Error::InvalidWord),
+        f.eval("variable v ' v >body")
+    );
+    assert_eq!(
+        Err(Error::UnknownWord),
+        f.eval("' nothing")
+    );
+}
+
+#[test]
+fn print_numbers() {
+    let mut f = Forth::new();
+    f.eval("1 2 . . -3 .");
+    assert_eq!("2 1 -3 ", f.take_output());
+    assert_eq!("", f.format_stack());
+}
+
+#[test]
+fn print_unsigned() {
+    let mut f = Forth::new();
+    f.eval("-1 u. 5 u.");
+    assert_eq!("4294967295 5 ", f.take_output());
+}
+
+#[test]
+fn print_right_aligned() {
+    let mut f = Forth::new();
+    f.eval("42 5 .r -7 3 .r 123 1 .r");
+    assert_eq!("   42 -7123", f.take_output());
+}
+
+#[test]
+fn emit_and_spacing() {
+    let mut f = Forth::new();
+    f.eval(": greet 72 emit 105 emit ; greet space 3 spaces 33 emit cr -2 spaces");
+    assert_eq!("Hi    !\n", f.take_output());
+}
+
+#[test]
+fn type_from_data_space() {
+    let mut f = Forth::new();
+    f.eval("create s 102 c, 111 c, 114 c, 116 c, 104 c,");
+    f.eval("s 5 type s 1+ 2 type");
+    assert_eq!("forthor", f.take_output());
+    assert_eq!(
+        Err(Error::InvalidAddress),
+        f.eval("s 6 type")
+    );
+}
+
+#[test]
+fn take_output_clears_it() {
+    let mut f = Forth::new();
+    f.eval("1 .");
+    assert_eq!("1 ", f.take_output());
+    assert_eq!("", f.take_output());
+}
+
+#[test]
+fn print_errors() {
+    let mut f = Forth::new();
+    assert_eq!(
+        Err(Error::StackUnderflow),
+        f.eval(".")
+    );
+    assert_eq!(
+        Err(Error::StackUnderflow),
+        f.eval("1 .r")
+    );
+}
+
+#[test]
+fn huge_padding_obeys_step_limit() {
+    let mut f = Forth::<i64>::default();
+    f.set_step_limit(Some(100));
+    assert_eq!(
+        Err(Error::StepLimitExceeded),
+        f.eval("99999999999999 spaces")
+    );
+    assert_eq!(
+        Err(Error::StepLimitExceeded),
+        f.eval("1 99999999999999 .r")
+    );
+    let mut f = Forth::<i64>::default();
+    f.set_step_limit(Some(100));
+    assert_eq!(Ok(()), f.eval("1000 spaces 7 600 .r"));
+    assert_eq!(1600, f.take_output().len());
+}
+
+#[derive(Clone)]
+struct SharedSink(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+struct BrokenSink;
+
+impl Write for BrokenSink {
+    fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+        Err(io::Error::new(io::ErrorKind::BrokenPipe, "closed"))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn output_sink() {
+    let mut f = Forth::new();
+    let sink = SharedSink(Arc::new(Mutex::new(Vec::new())));
+    f.set_output(sink.clone());
+    f.eval("1 . 2 .");
+    assert_eq!(b"1 2 ".to_vec(), *sink.0.lock().unwrap());
+    assert_eq!("", f.take_output());
+}
+
+#[test]
+fn output_sink_failure() {
+    let mut f = Forth::new();
+    f.set_output(BrokenSink);
+    assert_eq!(
+        Err(Error::Io(io::ErrorKind::BrokenPipe)),
+        f.eval("1 .")
+    );
+    assert_eq!("1", f.format_stack());
+}
+
+#[test]
+fn dot_quote_prints_verbatim() {
+    let mut f = Forth::new();
+    f.eval(".\" Hello,  World\" cr");
+    f.eval(": greet .\" Hi, \" .\" there!\" ; greet greet");
+    assert_eq!("Hello,  World\nHi, there!Hi, there!", f.take_output());
+}
+
+#[test]
+fn dot_paren_prints_at_once() {
+    let mut f = Forth::new();
+    f.eval(": foo .( Compiling) 1 ;");
+    assert_eq!("Compiling", f.take_output());
+    f.eval("foo .( Done)");
+    assert_eq!("Done", f.take_output());
+    assert_eq!("1", f.format_stack());
+}
+
+#[test]
+fn s_quote_in_data_space() {
+    let mut f = Forth::new();
+    f.eval("s\" Forth\" swap c@ here");
+    assert_eq!("5 70 5", f.format_stack());
+    f.eval("drop drop drop : name s\" MiXeD case\" ; name type name drop name drop =");
+    assert_eq!("MiXeD case", f.take_output());
+    assert_eq!("-1", f.format_stack());
+}
+
+#[test]
+fn c_quote_counted_string() {
+    let mut f = Forth::new();
+    f.eval(": hello c\" hello\" ; hello dup c@ swap 1+ c@");
+    assert_eq!("5 104", f.format_stack());
+    f.eval("c\"  \" c@");
+    assert_eq!("5 104 1", f.format_stack());
+    let long = format!("c\" {}\"", "x".repeat(256));
+    assert_eq!(
+        Err(Error::InvalidWord),
+        f.eval(&long)
+    );
+}
+
+#[test]
+fn words_are_case_insensitive_outside_strings() {
+    let mut f = Forth::new();
+    f.eval(": Shout .\" Loud\" ; shout SHOUT");
+    assert_eq!("LoudLoud", f.take_output());
+}
+
+#[test]
+fn unterminated_string_takes_rest_of_input() {
+    let mut f = Forth::new();
+    f.eval(".\" no end");
+    assert_eq!("no end", f.take_output());
+    f.eval("s\"");
+    assert_eq!("0 0", f.format_stack());
+}
+
+#[test]
+fn paren_comments() {
+    let mut f = Forth::new();
+    f.eval("( n -- n*n ) : square ( n -- n*n ) dup * ; 3 square ( 9 )");
+    assert_eq!("9", f.format_stack());
+    f.eval(": x ( a\nb ) 1 ( ; ) ; x");
+    assert_eq!("9 1", f.format_stack());
+}
+
+#[test]
+fn paren_comment_across_eval_calls() {
+    let mut f = Forth::new();
+    f.eval(": foo ( n --");
+    f.eval("  2 3 + . ;");
+    f.eval("n ) 5 ;");
+    f.eval("foo");
+    assert_eq!("5", f.format_stack());
+    assert!(!f.is_compiling());
+}
+
+#[test]
+fn line_comments() {
+    let mut f = Forth::new();
+    f.eval("1 \\ 2 3\n4 : bar \\ comment ;\n 5 ; bar \\");
+    assert_eq!("1 4 5", f.format_stack());
+}
+
+#[test]
+fn comment_words_need_a_space() {
+    let mut f = Forth::new();
+    assert_eq!(
+        Err(Error::UnknownWord),
+        f.eval("(comment)")
+    );
+}
+
+#[test]
+fn abort_definition_ends_comment() {
+    let mut f = Forth::new();
+    f.eval("1 ( unclosed");
+    f.abort_definition();
+    f.eval("2 ) 3");
+    assert_eq!("1 2", f.format_stack());
+}
+
+#[test]
+fn error_location_of_word() {
+    let mut f = Forth::new();
+    let err = f.eval("1 2 +\n  3 Frob 4").unwrap_err();
+    assert_eq!(Error::UnknownWord, err);
+    let loc = err.location().unwrap();
+    assert_eq!("Frob", loc.token);
+    assert_eq!(10..14, loc.span);
+    assert_eq!((2, 5), (loc.line, loc.column));
+    assert!(loc.calls.is_empty());
+}
+
+#[test]
+fn located_errors_compare_by_kind() {
+    let mut f = Forth::new();
+    let err = f.eval("drop").unwrap_err();
+    assert_eq!(&Error::StackUnderflow, err.kind());
+    assert_eq!(-4, err.code());
+    assert_eq!(err, f.eval("1 + drop").unwrap_err());
+    assert!(err != f.eval("1 0 /").unwrap_err());
+    assert!(Error::StackUnderflow.location().is_none());
+}
+
+#[test]
+fn located_error_messages() {
+    let mut f = Forth::new();
+    f.eval(": inner 0 / ; : outer 1 inner ;");
+    assert_eq!(
+        "2:3: outer: division by zero in OUTER > INNER",
+        f.eval("\n  outer").unwrap_err().to_string()
+    );
+    assert_eq!(
+        "1:5: return stack imbalance",
+        f.eval("1 >r").unwrap_err().to_string()
+    );
+}
+
+#[test]
+fn error_location_columns_count_characters() {
+    let mut f = Forth::new();
+    let err = f.eval(".\" héllo\" 0 0 /").unwrap_err();
+    let loc = err.location().unwrap();
+    assert_eq!("/", loc.token);
+    assert_eq!(15..16, loc.span);
+    assert_eq!((1, 15), (loc.line, loc.column));
+}
+
+#[test]
+fn error_location_call_chain() {
+    let mut f = Forth::new();
+    f.eval(": inner 0 / ;");
+    f.eval(": middle 1 inner ;");
+    f.eval(": outer middle ;");
+    let err = f.eval("outer").unwrap_err();
+    assert_eq!(Error::DivisionByZero, err);
+    let loc = err.location().unwrap();
+    assert_eq!("outer", loc.token);
+    assert_eq!(vec!["OUTER", "MIDDLE", "INNER"], loc.calls);
+}
+
+#[test]
+fn error_location_call_chain_keeps_both_ends() {
+    let mut f = Forth::new();
+    f.eval(": leaf 0 / ; : deep dup if 1- recurse else leaf then ; : top 20 deep ;");
+    let err = f.eval("top").unwrap_err();
+    assert_eq!(Error::DivisionByZero, err);
+    let loc = err.location().unwrap();
+    let mut calls = vec!["TOP"];
+    calls.extend(vec!["DEEP"; 14]);
+    calls.push("LEAF");
+    assert_eq!(calls, loc.calls);
+    assert_eq!(7, loc.calls_omitted);
+    f.eval(": inf recurse ;");
+    let err = f.eval("inf").unwrap_err();
+    assert_eq!(Error::CallDepthExceeded, err);
+    assert_eq!(16, err.location().unwrap().calls.len());
+}
+
+#[test]
+fn error_location_call_chain_of_created_word() {
+    let mut f = Forth::new();
+    f.eval(": checked create , does> @ 0 / ;");
+    f.eval("5 checked five : use-it five ;");
+    let err = f.eval("use-it").unwrap_err();
+    assert_eq!(Error::DivisionByZero, err);
+    assert_eq!(vec!["USE-IT", "FIVE"], err.location().unwrap().calls);
+}
+
+#[test]
+fn error_location_call_chain_of_unbalanced_word() {
+    let mut f = Forth::new();
+    f.eval(": bad 1 >r ; : caller bad ;");
+    let err = f.eval("caller").unwrap_err();
+    assert_eq!(Error::ReturnStackImbalance, err);
+    assert_eq!(vec!["CALLER", "BAD"], err.location().unwrap().calls);
+}
+
+#[test]
+fn error_location_call_chain_leaves_out_built_ins() {
+    let mut f = Forth::new();
+    let err = f.eval("' dup execute").unwrap_err();
+    assert_eq!(Error::StackUnderflow, err);
+    assert!(err.location().unwrap().calls.is_empty());
+    f.eval(": run execute ;");
+    let err = f.eval("' drop run").unwrap_err();
+    assert_eq!(Error::StackUnderflow, err);
+    assert_eq!(vec!["RUN"], err.location().unwrap().calls);
+}
+
+#[test]
+fn error_location_in_definition() {
+    let mut f = Forth::new();
+    let err = f.eval(": foo\n  if 1 ;").unwrap_err();
+    assert_eq!(Error::InvalidWord, err);
+    let loc = err.location().unwrap();
+    assert_eq!(";", loc.token);
+    assert_eq!((2, 8), (loc.line, loc.column));
+}
+
+#[test]
+fn error_location_at_end_of_input() {
+    let mut f = Forth::new();
+    let err = f.eval("1 >r ").unwrap_err();
+    assert_eq!(Error::ReturnStackImbalance, err);
+    let loc = err.location().unwrap();
+    assert_eq!("", loc.token);
+    assert_eq!(5..5, loc.span);
+}
+
+#[test]
+fn error_messages() {
+    assert_eq!("stack underflow", Error::StackUnderflow.to_string());
+    assert_eq!("division by zero", Error::DivisionByZero.to_string());
+    assert_eq!("undefined word", Error::UnknownWord.to_string());
+    assert_eq!(
+        "output error: broken pipe",
+        Error::Io(io::ErrorKind::BrokenPipe).to_string()
+    );
+}
+
+#[test]
+fn error_codes() {
+    assert_eq!(-4, Error::StackUnderflow.code());
+    assert_eq!(-10, Error::DivisionByZero.code());
+    assert_eq!(-13, Error::UnknownWord.code());
+    for code in -100..100 {
+        if let Some(e) = Error::from_code(code) {
+            assert_eq!(code, e.code());
+        }
+    }
+    assert_eq!(Some(Error::StackUnderflow), Error::from_code(-4));
+    assert_eq!(None, Error::from_code(-1));
+    assert_eq!(None, Error::from_code(7));
+}
+
+#[test]
+fn errors_work_with_question_mark() {
+    fn run(f: &mut Forth) -> Result<(), Box<dyn std::error::Error>> {
+        f.eval("1 0 /")?;
+        Ok(())
+    }
+    let e = run(&mut Forth::new()).unwrap_err();
+    assert_eq!("1:5: /: division by zero", e.to_string());
+    let e = e.downcast::<Error>().unwrap();
+    assert_eq!(Error::DivisionByZero, *e);
+    assert_eq!(4..5, e.location().unwrap().span);
+}
+
+#[test]
+fn catch_without_throw() {
+    let mut f = Forth::new();
+    f.eval(": add1 1 + ; 5 ' add1 catch");
+    assert_eq!("6 0", f.format_stack());
+}
+
+#[test]
+fn catch_user_throw() {
+    let mut f = Forth::new();
+    f.eval(": risky ( n -- ) 10 > if 42 throw then ;");
+    f.eval("1 2 3 ' risky catch");
+    assert_eq!("1 2 0", f.format_stack());
+    f.eval("drop 20 ' risky catch");
+    assert_eq!("1 2 0 42", f.format_stack());
+}
+
+#[test]
+fn catch_restores_stack_depth() {
+    let mut f = Forth::new();
+    f.eval(": messy 1 2 3 >r 4 5 0 throw 6 7 -99 throw ; 10 ' messy catch");
+    assert_eq!("10 -99", f.format_stack());
+    f.eval(": eat drop drop drop 1 throw ; 1 2 3 ' eat catch");
+    assert_eq!("10 -99 0 0 0 1", f.format_stack());
+    assert!(f.return_stack().is_empty());
+}
+
+#[test]
+fn catch_errors_by_code() {
+    let mut f = Forth::new();
+    f.eval(": divide / ; 1 0 ' divide catch");
+    assert_eq!("1 0 -10", f.format_stack());
+    f.eval("2drop drop ' drop catch");
+    assert_eq!("-4", f.format_stack());
+}
+
+#[test]
+fn throw_zero_does_nothing() {
+    let mut f = Forth::new();
+    f.eval("1 0 throw");
+    assert_eq!("1", f.format_stack());
+}
+
+#[test]
+fn uncaught_throw() {
+    let mut f = Forth::new();
+    assert_eq!(Err(Error::Throw(7)), f.eval("1 7 throw"));
+    assert_eq!("1", f.format_stack());
+    assert_eq!(Err(Error::StackUnderflow), f.eval("-4 throw"));
+    let err = f.eval(": t 7 throw ; t").unwrap_err();
+    assert_eq!(Error::Throw(7), err);
+    assert_eq!(vec!["T"], err.location().unwrap().calls);
+}
+
+#[test]
+fn nested_catch() {
+    let mut f = Forth::new();
+    f.eval(": inner 5 throw ; ' inner constant inner-xt");
+    f.eval(": outer inner-xt catch 1+ throw ; ' outer catch");
+    assert_eq!("6", f.format_stack());
+}
+
+#[test]
+fn abort_clears_stack() {
+    let mut f = Forth::new();
+    assert_eq!(Err(Error::Throw(-1)), f.eval("1 2 abort 3"));
+    assert_eq!("", f.format_stack());
+    f.eval(": a abort ; ' a catch");
+    assert_eq!("-1", f.format_stack());
+}
+
+#[test]
+fn abort_quote() {
+    let mut f = Forth::new();
+    f.eval(": check ( n -- ) 0< abort\" Negative!\" ;");
+    f.eval("1 check 5");
+    assert_eq!("5", f.format_stack());
+    assert_eq!(Err(Error::Throw(-2)), f.eval("-1 check"));
+    assert_eq!("Negative!", f.take_output());
+    assert_eq!("", f.format_stack());
+    f.eval("7 -1 ' check catch");
+    assert_eq!("7 -1 -2", f.format_stack());
+    assert_eq!("", f.take_output());
+}
+
+#[test]
+fn abort_quote_is_compile_only() {
+    let mut f = Forth::new();
+    assert_eq!(
+        Err(Error::InvalidWord),
+        f.eval("1 abort\" no\"")
+    );
+}
+
+#[test]
+fn step_limit_cannot_be_caught() {
+    let mut f = Forth::new();
+    f.set_step_limit(Some(1000));
+    f.eval(": spin begin again ;");
+    assert_eq!(
+        Err(Error::StepLimitExceeded),
+        f.eval("' spin catch")
+    );
+}
+
+#[test]
+fn throw_messages() {
+    assert_eq!("aborted", Error::Throw(-1).to_string());
+    assert_eq!("uncaught exception 42", Error::Throw(42).to_string());
+    assert_eq!(42, Error::Throw(42).code());
+}
+
+#[test]
+fn execute_tick() {
+    let mut f = Forth::new();
+    f.eval("3 4 ' + execute : sq dup * ; ' sq execute");
+    assert_eq!("49", f.format_stack());
+}
+
+#[test]
+fn bracket_tick_compiles_token() {
+    let mut f = Forth::new();
+    f.eval(": sq dup * ; : apply-sq ['] sq execute ; 5 apply-sq");
+    assert_eq!("25", f.format_stack());
+    assert_eq!(
+        Err(Error::InvalidWord),
+        f.eval("['] sq")
+    );
+    assert_eq!(
+        Err(Error::UnknownWord),
+        f.eval(": bad ['] nothing ;")
+    );
+    assert_eq!(
+        Err(Error::InvalidWord),
+        f.eval(": bad ['] if ;")
+    );
+}
+
+#[test]
+fn higher_order_words() {
+    let mut f = Forth::new();
+    f.eval(": twice ( xt -- ) dup >r execute r> execute ;");
+    f.eval(": inc 1+ ; 5 ' inc twice");
+    assert_eq!("7", f.format_stack());
+}
+
+#[test]
+fn dispatch_table_in_data_space() {
+    let mut f = Forth::new();
+    f.eval(": one 1 ; : two 2 ; : three 3 ;");
+    f.eval("create ops ' one , ' two , ' three ,");
+    f.eval(": dispatch ( n -- ) cells ops + @ execute ;");
+    f.eval("2 dispatch 0 dispatch 1 dispatch");
+    assert_eq!("3 1 2", f.format_stack());
+}
+
+#[test]
+fn execute_keeps_redefined_words() {
+    let mut f = Forth::new();
+    f.eval(": foo 1 ; ' foo : foo 2 ; execute foo");
+    assert_eq!("1 2", f.format_stack());
+}
+
+#[test]
+fn execute_invalid_token() {
+    let mut f = Forth::new();
+    f.eval(": foo 1 2 + ;");
+    assert_eq!(
+        Err(Error::InvalidExecutionToken),
+        f.eval("' foo 1+ execute")
+    );
+    assert_eq!(
+        Err(Error::InvalidExecutionToken),
+        f.eval("-1 execute")
+    );
+    assert_eq!(
+        Err(Error::InvalidExecutionToken),
+        f.eval("0 ' catch execute")
+    );
+    assert_eq!(
+        Err(Error::StackUnderflow),
+        Forth::new().eval("execute")
+    );
+}